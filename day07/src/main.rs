@@ -1,25 +1,72 @@
 use itertools::Itertools;
 
-fn linear_fuel(crab: u32, pos: u32) -> u32 {
-    (crab as i32 - pos as i32).abs() as u32
+/// Describes how much fuel a crab engine burns to move a given distance.
+trait FuelModel {
+    fn cost(&self, distance: u32) -> u64;
 }
 
-fn expensive_fuel(crab: u32, pos: u32) -> u32 {
-    let diff = (crab as i32 - pos as i32).abs() as u32;
-    ((diff + 1) * diff) / 2
+/// Every step costs 1 fuel.
+struct Linear;
+
+impl FuelModel for Linear {
+    fn cost(&self, distance: u32) -> u64 {
+        distance as u64
+    }
+}
+
+/// Each step costs 1 more fuel than the previous, n-th step costs n.
+struct Triangular;
+
+impl FuelModel for Triangular {
+    fn cost(&self, distance: u32) -> u64 {
+        let distance = distance as u64;
+        (distance + 1) * distance / 2
+    }
+}
+
+/// Fuel grows with the square of the distance.
+struct Quadratic;
+
+impl FuelModel for Quadratic {
+    fn cost(&self, distance: u32) -> u64 {
+        let distance = distance as u64;
+        distance * distance
+    }
+}
+
+/// Limits the fuel of another model per crab to a maximum.
+struct Capped<M> {
+    model: M,
+    max: u64,
+}
+
+impl<M: FuelModel> FuelModel for Capped<M> {
+    fn cost(&self, distance: u32) -> u64 {
+        self.model.cost(distance).min(self.max)
+    }
+}
+
+/// Allows any closure to be used as a custom engine.
+impl<F> FuelModel for F
+where
+    F: Fn(u32) -> u64,
+{
+    fn cost(&self, distance: u32) -> u64 {
+        self(distance)
+    }
 }
 
-fn find_optimal_pos<F>(positions: &[u32], fuel_fn: F) -> (u32, u32)
+fn find_optimal_pos<M>(positions: &[u32], model: M) -> (u32, u128)
 where
-    F: Fn(u32, u32) -> u32,
+    M: FuelModel,
 {
     let (&min, &max) = positions.iter().minmax().into_option().unwrap();
     (min..=max)
         .map(|pos| {
             let fuel = positions
                 .iter()
-                .map(|&crab| fuel_fn(crab, pos))
-                .sum::<u32>();
+                .map(|&crab| model.cost(crab.abs_diff(pos)) as u128)
+                .sum::<u128>();
             (pos, fuel)
         })
         .min_by_key(|(_, fuel)| *fuel)
@@ -37,26 +84,63 @@ fn parse_input(input: &str) -> Vec<u32> {
 fn main() {
     let positions = parse_input(include_str!("input.txt"));
 
-    let (pos, fuel) = find_optimal_pos(&positions, linear_fuel);
+    let (pos, fuel) = find_optimal_pos(&positions, Linear);
     dbg!(pos, fuel);
 
-    let (pos, fuel) = find_optimal_pos(&positions, expensive_fuel);
+    let (pos, fuel) = find_optimal_pos(&positions, Triangular);
+    dbg!(pos, fuel);
+
+    let (pos, fuel) = find_optimal_pos(&positions, Quadratic);
+    dbg!(pos, fuel);
+
+    let capped = Capped {
+        model: Triangular,
+        max: 1_000,
+    };
+    let (pos, fuel) = find_optimal_pos(&positions, capped);
     dbg!(pos, fuel);
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{expensive_fuel, find_optimal_pos, linear_fuel};
+    use crate::{find_optimal_pos, Capped, FuelModel, Linear, Quadratic, Triangular};
 
     const INPUT: [u32; 10] = [16, 1, 2, 0, 4, 2, 7, 1, 2, 14];
 
     #[test]
     fn test_find_linear_pos() {
-        assert_eq!((2, 37), find_optimal_pos(&INPUT, linear_fuel));
+        assert_eq!((2, 37), find_optimal_pos(&INPUT, Linear));
     }
 
     #[test]
     fn test_find_pos_using_expensive_move() {
-        assert_eq!((5, 168), find_optimal_pos(&INPUT, expensive_fuel));
+        assert_eq!((5, 168), find_optimal_pos(&INPUT, Triangular));
+    }
+
+    #[test]
+    fn test_find_pos_using_quadratic_move() {
+        assert_eq!((5, 291), find_optimal_pos(&INPUT, Quadratic));
+    }
+
+    #[test]
+    fn test_find_pos_using_capped_move() {
+        let model = Capped {
+            model: Triangular,
+            max: 10,
+        };
+        assert_eq!((2, 38), find_optimal_pos(&INPUT, model));
+    }
+
+    #[test]
+    fn test_find_pos_using_custom_move() {
+        let model = |distance: u32| 2 * distance as u64;
+        assert_eq!((2, 74), find_optimal_pos(&INPUT, model));
+    }
+
+    #[test]
+    fn test_fuel_does_not_overflow() {
+        let max = u32::MAX as u64;
+        assert_eq!(max * (max + 1) / 2, Triangular.cost(u32::MAX));
+        assert_eq!(max * max, Quadratic.cost(u32::MAX));
     }
 }
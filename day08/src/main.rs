@@ -1,42 +1,255 @@
-use std::{collections::HashMap, ops::Shl};
+use std::ops::Shl;
 
+use anyhow::anyhow;
 use itertools::Itertools;
 
+/// Number of output digits each note line shows.
+const OUTPUT_DIGITS: usize = 4;
+
 struct DisplayLine {
-    pub segments: Vec<u16>,
-    pub digits: Vec<u16>,
+    pub segments: Vec<u32>,
+    pub digits: Vec<u32>,
 }
 
-fn parse_binary(val: &str) -> u16 {
-    val.chars().fold(0_u16, |mut result, char| {
-        let pos = (char as u32 - 'a' as u32) as u16;
-        result |= 1_u16.shl(pos);
+fn parse_binary(val: &str) -> u32 {
+    val.chars().fold(0_u32, |mut result, char| {
+        let pos = char as u32 - 'a' as u32;
+        result |= 1_u32.shl(pos);
         result
     })
 }
 
-/// Calculates the difference in bits.
-fn diff(lhs: u16, rhs: u16) -> u16 {
-    let mut lhs = lhs.clone();
-    for bit_pos in 0..=6_u16 {
-        if rhs & 1_u16.shl(bit_pos) > 0 {
-            lhs &= !1_u16.shl(bit_pos);
+/// Parses a single pattern, checks that every segment letter is valid and lit only once.
+fn parse_pattern(val: &str) -> anyhow::Result<u32> {
+    val.chars().try_fold(0_u32, |result, char| {
+        if !char.is_ascii_lowercase() {
+            return Err(anyhow!("Invalid segment letter '{}' in '{}'", char, val));
+        }
+        let bit = 1_u32.shl(char as u32 - 'a' as u32);
+        if result & bit > 0 {
+            return Err(anyhow!("Segment '{}' appears twice in '{}'", char, val));
         }
+        Ok(result | bit)
+    })
+}
+
+/// Iterates over the positions of all set bits in the given mask.
+fn bits(mask: u32) -> impl Iterator<Item = usize> {
+    (0..32).filter(move |&pos| mask & 1_u32.shl(pos) > 0)
+}
+
+/// Describes which segments are lit for every digit of a display.
+///
+/// The index in `digits` is the value of the digit, each entry is a bit mask of its segments.
+/// Any number of segments (up to 32) is supported, e.g. 14- or 16-segment alphanumerics.
+struct SegmentLayout {
+    pub digits: Vec<u32>,
+    pub num_segments: usize,
+}
+
+impl SegmentLayout {
+    pub fn new(digits: Vec<u32>) -> Self {
+        let num_segments = digits
+            .iter()
+            .map(|digit| 32 - digit.leading_zeros())
+            .max()
+            .unwrap_or(0) as usize;
+        Self {
+            digits,
+            num_segments,
+        }
+    }
+
+    /// Returns the standard 7-segment display, segments are labeled as follows:
+    ///
+    /// ```text
+    ///  aaaa
+    /// b    c
+    /// b    c
+    ///  dddd
+    /// e    f
+    /// e    f
+    ///  gggg
+    /// ```
+    pub fn seven_segment() -> Self {
+        let digits = [
+            "abcefg", "cf", "acdeg", "acdfg", "bcdf", "abdfg", "abdefg", "acf", "abcdefg", "abcdfg",
+        ];
+        Self::new(
+            digits
+                .iter()
+                .map(|&digit| parse_binary(digit))
+                .collect_vec(),
+        )
     }
-    lhs
+
+    fn all_segments(&self) -> u32 {
+        (1_u64.shl(self.num_segments) - 1) as u32
+    }
+}
+
+/// The outcome of deducing the wiring of a display line.
+#[derive(Debug, PartialEq)]
+enum Deduction {
+    /// The only wiring that matches all patterns, maps each wire to its segment.
+    Solved(Vec<usize>),
+    /// More than one wiring matches all patterns.
+    Ambiguous,
+    /// No wiring matches all patterns.
+    Inconsistent,
 }
 
-/// This extracts the single element under the given key
-/// The list requires to be of length 1.
-fn remove_element<F>(table: &mut HashMap<u32, Vec<u16>>, index: u32, bit_fn: F) -> u16
-where
-    F: FnMut(&&u16) -> bool,
-{
-    let (pos, &digit) = table[&index].iter().find_position(bit_fn).unwrap();
-    if let Some(list) = table.get_mut(&index) {
-        list.remove(pos);
+/// Deduces the wire to segment permutation by constraint propagation.
+///
+/// Every wire keeps a bit mask of segments it still can be connected to. Each pattern restricts
+/// its wires to the segments of digits with the same number of segments and vice versa.
+/// When propagation gets stuck the solver guesses the wire with the fewest candidates.
+struct WiringSolver<'a> {
+    layout: &'a SegmentLayout,
+    patterns: &'a [u32],
+}
+
+impl<'a> WiringSolver<'a> {
+    pub fn new(layout: &'a SegmentLayout, patterns: &'a [u32]) -> Self {
+        Self { layout, patterns }
+    }
+
+    pub fn solve(&self) -> Deduction {
+        let wires = self.layout.num_segments;
+        if self
+            .patterns
+            .iter()
+            .any(|&pattern| pattern.checked_shr(wires as u32).unwrap_or(0) != 0)
+        {
+            return Deduction::Inconsistent;
+        }
+
+        let mut solutions = Vec::new();
+        self.search(vec![self.layout.all_segments(); wires], &mut solutions);
+
+        match solutions.len() {
+            0 => Deduction::Inconsistent,
+            1 => Deduction::Solved(solutions.remove(0)),
+            _ => Deduction::Ambiguous,
+        }
     }
-    digit
+
+    /// Searches for up to two solutions, enough to tell if the wiring is unique.
+    fn search(&self, mut candidates: Vec<u32>, solutions: &mut Vec<Vec<usize>>) {
+        if solutions.len() > 1 || !self.propagate(&mut candidates) {
+            return;
+        }
+
+        let open_wire = candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, mask)| mask.count_ones() > 1)
+            .min_by_key(|(_, mask)| mask.count_ones());
+
+        match open_wire {
+            Some((wire, &mask)) => {
+                for segment in bits(mask) {
+                    let mut guess = candidates.clone();
+                    guess[wire] = 1_u32.shl(segment);
+                    self.search(guess, solutions);
+                }
+            }
+            None => {
+                let wiring = candidates
+                    .iter()
+                    .map(|mask| mask.trailing_zeros() as usize)
+                    .collect_vec();
+                if self.is_valid(&wiring) {
+                    solutions.push(wiring);
+                }
+            }
+        }
+    }
+
+    /// Narrows down the candidates until nothing changes, returns false on a contradiction.
+    fn propagate(&self, candidates: &mut [u32]) -> bool {
+        let all_segments = self.layout.all_segments();
+
+        loop {
+            let before = candidates.to_vec();
+
+            for &pattern in self.patterns {
+                let reachable = bits(pattern).fold(0, |mask, wire| mask | candidates[wire]);
+                let matching = self
+                    .layout
+                    .digits
+                    .iter()
+                    .filter(|&&digit| {
+                        digit.count_ones() == pattern.count_ones() && digit & !reachable == 0
+                    })
+                    .collect_vec();
+
+                let lit = matching.iter().fold(0, |mask, &&digit| mask | digit);
+                let unlit = matching
+                    .iter()
+                    .fold(0, |mask, &&digit| mask | (!digit & all_segments));
+
+                for (wire, mask) in candidates.iter_mut().enumerate() {
+                    if pattern & 1_u32.shl(wire) > 0 {
+                        *mask &= lit;
+                    } else {
+                        *mask &= unlit;
+                    }
+                }
+            }
+
+            // a wire with a single segment left owns that segment
+            for wire in 0..candidates.len() {
+                let mask = candidates[wire];
+                if mask.count_ones() == 1 {
+                    for (other, other_mask) in candidates.iter_mut().enumerate() {
+                        if other != wire {
+                            *other_mask &= !mask;
+                        }
+                    }
+                }
+            }
+
+            // a segment only reachable by a single wire belongs to it
+            for segment in bits(all_segments) {
+                let segment = 1_u32.shl(segment);
+                let wires = (0..candidates.len())
+                    .filter(|&wire| candidates[wire] & segment > 0)
+                    .collect_vec();
+                match wires[..] {
+                    [] => return false,
+                    [wire] => candidates[wire] = segment,
+                    _ => (),
+                }
+            }
+
+            if candidates.contains(&0) {
+                return false;
+            }
+            if candidates == &before[..] {
+                return true;
+            }
+        }
+    }
+
+    /// Checks that every pattern maps to a different digit.
+    fn is_valid(&self, wiring: &[usize]) -> bool {
+        let digits = self
+            .patterns
+            .iter()
+            .map(|&pattern| decode(self.layout, wiring, pattern))
+            .collect::<Option<Vec<_>>>();
+        match digits {
+            Some(digits) => digits.iter().all_unique(),
+            None => false,
+        }
+    }
+}
+
+/// Returns the digit the given wires show using the wiring.
+fn decode(layout: &SegmentLayout, wiring: &[usize], wires: u32) -> Option<usize> {
+    let segments = bits(wires).fold(0, |mask, wire| mask | 1_u32.shl(wiring[wire]));
+    layout.digits.iter().position(|&digit| digit == segments)
 }
 
 impl DisplayLine {
@@ -48,128 +261,176 @@ impl DisplayLine {
             .count()
     }
 
-    /// Returns the four digit value of this display, deduced / analyzed by the given segments
-    /// Segments are analyzed as follows:
-    ///
-    /// ```
-    ///  0000
-    /// 1    2
-    /// 1    2
-    ///  3333
-    /// 4    5
-    /// 4    5
-    ///  6666
-    /// ```
-    ///
-    /// Analyzing number of occurences of segments in all digits
-    /// * `0` appears 8 times
-    /// * `1` appears 6 times
-    /// * `2` appears 8 times
-    /// * `3` appears 7 times
-    /// * `4` appears 4 times
-    /// * `5` appears 9 times
-    /// * `6` appears 7 times
-    ///
-    pub fn deduce_digits(&self) -> u32 {
-        // let table = self.segments.iter().map(|digit| (digit.count_ones(), digit)).collect::<HashMap<_, Vec<_>>>();
-        let mut table = self
+    /// Deduces how the wires of this line are connected to the segments of the given layout.
+    pub fn deduce_wiring(&self, layout: &SegmentLayout) -> Deduction {
+        WiringSolver::new(layout, &self.segments).solve()
+    }
+
+    /// Checks that this line fits the given layout, one pattern per digit and valid segments only.
+    pub fn validate(&self, layout: &SegmentLayout) -> anyhow::Result<()> {
+        if self.segments.len() != layout.digits.len() {
+            return Err(anyhow!(
+                "Expected {} patterns, found {}",
+                layout.digits.len(),
+                self.segments.len()
+            ));
+        }
+        if self.digits.len() != OUTPUT_DIGITS {
+            return Err(anyhow!(
+                "Expected {} output digits, found {}",
+                OUTPUT_DIGITS,
+                self.digits.len()
+            ));
+        }
+        if let Some(pattern) = self.segments.iter().duplicates().next() {
+            return Err(anyhow!("Duplicate pattern '{}'", format_pattern(*pattern)));
+        }
+        let all_segments = layout.all_segments();
+        if let Some(&pattern) = self
             .segments
             .iter()
-            .fold(HashMap::new(), |mut table, digit| {
-                table
-                    .entry(digit.count_ones())
-                    .or_insert(Vec::new())
-                    .push(digit.clone());
-                table
-            });
-
-        let one = table.remove(&2).unwrap()[0];
-        let four = table.remove(&4).unwrap()[0];
-        let seven = table.remove(&3).unwrap()[0];
-        let eight = table.remove(&7).unwrap()[0];
-        let three = remove_element(&mut table, 5, |&&digit| {
-            diff(digit, seven).count_ones() == 2
-        });
-        let nine = remove_element(&mut table, 6, |&&digit| (three ^ digit).count_ones() == 1);
-        let six = remove_element(&mut table, 6, |&&digit| {
-            (one & (diff(eight, digit))).count_ones() == 1
-        });
-        let zero = table.remove(&6).unwrap()[0];
-        let five = remove_element(&mut table, 5, |&&digit| (six - digit).count_ones() == 1);
-        let two = table.remove(&5).unwrap()[0];
-
-        // hacky version to get final sum
-        let list = vec![zero, one, two, three, four, five, six, seven, eight, nine];
-        let four_digits = self
-            .digits
+            .chain(self.digits.iter())
+            .find(|&&pattern| pattern & !all_segments != 0)
+        {
+            let invalid = bits(pattern & !all_segments).next().unwrap_or_default();
+            return Err(anyhow!(
+                "Invalid segment letter '{}' in '{}'",
+                segment_letter(invalid),
+                format_pattern(pattern)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the output digits of this line, if the wiring can be deduced.
+    pub fn decode(&self, layout: &SegmentLayout) -> anyhow::Result<Vec<usize>> {
+        let wiring = match self.deduce_wiring(layout) {
+            Deduction::Solved(wiring) => wiring,
+            Deduction::Ambiguous => return Err(anyhow!("Wiring is ambiguous")),
+            Deduction::Inconsistent => return Err(anyhow!("No consistent wiring found")),
+        };
+        self.digits
             .iter()
-            .map(|digit| {
-                let (val, _) = list.iter().find_position(|&val| val == digit).unwrap();
-                val.to_string()
+            .map(|&digit| {
+                decode(layout, &wiring, digit).ok_or_else(|| {
+                    anyhow!("Output '{}' is not a known digit", format_pattern(digit))
+                })
             })
-            .join("")
-            .parse::<u32>()
-            .unwrap();
+            .collect()
+    }
 
-        four_digits
+    /// Returns the four digit value of this display, deduced / analyzed by the given segments
+    pub fn deduce_digits(&self) -> anyhow::Result<u32> {
+        let digits = self.decode(&SegmentLayout::seven_segment())?;
+        Ok(digits
+            .iter()
+            .fold(0, |value, &digit| value * 10 + digit as u32))
     }
 }
 
-impl From<&str> for DisplayLine {
-    fn from(line: &str) -> Self {
-        let (segments, digits) = line.split_once('|').expect("Failed to split line");
-        let segments = segments.split_whitespace().map(parse_binary).collect_vec();
-        let digits = digits.split_whitespace().map(parse_binary).collect_vec();
-        Self { segments, digits }
+fn segment_letter(segment: usize) -> char {
+    (b'a' + segment as u8) as char
+}
+
+fn format_pattern(pattern: u32) -> String {
+    bits(pattern).map(segment_letter).collect()
+}
+
+impl TryFrom<&str> for DisplayLine {
+    type Error = anyhow::Error;
+
+    fn try_from(line: &str) -> Result<Self, Self::Error> {
+        let (segments, digits) = line
+            .split_once('|')
+            .ok_or_else(|| anyhow!("Missing '|' between patterns and output digits"))?;
+        let segments = segments
+            .split_whitespace()
+            .map(parse_pattern)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let digits = digits
+            .split_whitespace()
+            .map(parse_pattern)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self { segments, digits })
     }
 }
 
 struct DisplayNotes {
-    pub lines: Vec<DisplayLine>,
+    /// Valid lines together with their line number
+    pub lines: Vec<(usize, DisplayLine)>,
+    /// Lines that failed to parse or validate
+    pub errors: Vec<anyhow::Error>,
 }
 
 impl DisplayNotes {
-    pub fn new(lines: Vec<DisplayLine>) -> Self {
-        Self { lines }
+    pub fn new(lines: Vec<(usize, DisplayLine)>, errors: Vec<anyhow::Error>) -> Self {
+        Self { lines, errors }
     }
 
     pub fn count_easy_digits(&self) -> usize {
         self.lines
             .iter()
-            .map(|line| line.count_easy_digits())
+            .map(|(_, line)| line.count_easy_digits())
             .sum::<usize>()
     }
 
-    pub fn count_deduced_digits(&self) -> u32 {
-        self.lines
+    /// Sums the values of all lines that can be deduced, returns the failed lines as well.
+    pub fn count_deduced_digits(&self) -> (u32, Vec<anyhow::Error>) {
+        let (values, failures): (Vec<_>, Vec<_>) = self
+            .lines
             .iter()
-            .map(|line| line.deduce_digits())
-            .sum::<u32>()
+            .map(|(number, line)| {
+                line.deduce_digits()
+                    .map_err(|error| error.context(format!("Failed to deduce line {}", number)))
+            })
+            .partition_result();
+        (values.iter().sum(), failures)
     }
 }
 
+/// Parses a single note line and checks it against the standard 7-segment display.
+fn parse_note(line: &str) -> anyhow::Result<DisplayLine> {
+    let line = DisplayLine::try_from(line)?;
+    line.validate(&SegmentLayout::seven_segment())?;
+    Ok(line)
+}
+
 fn parse_input(input: &str) -> DisplayNotes {
-    let lines = input
+    let (lines, errors): (Vec<_>, Vec<_>) = input
         .lines()
         .map(str::trim)
-        .filter(|&line| !line.is_empty())
-        .map(DisplayLine::from)
-        .collect_vec();
-    DisplayNotes::new(lines)
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(index, line)| {
+            let number = index + 1;
+            parse_note(line)
+                .map(|display| (number, display))
+                .map_err(|error| error.context(format!("Invalid note in line {}", number)))
+        })
+        .partition_result();
+    DisplayNotes::new(lines, errors)
 }
 
 fn main() {
     let notes = parse_input(include_str!("input.txt"));
+    for error in &notes.errors {
+        eprintln!("{:#}", error);
+    }
 
     dbg!(notes.count_easy_digits());
-    dbg!(notes.count_deduced_digits());
+
+    let (sum, failures) = notes.count_deduced_digits();
+    for error in &failures {
+        eprintln!("{:#}", error);
+    }
+    dbg!(sum);
 }
 
 #[cfg(test)]
 mod tests {
     use itertools::Itertools;
 
-    use crate::{parse_binary, parse_input, DisplayLine};
+    use crate::{parse_binary, parse_input, Deduction, DisplayLine, SegmentLayout, WiringSolver};
 
     const INPUT: &str = r#"
         be cfbegad cbdgef fgaecd cgeb fdcge agebfd fecdb fabcd edb | fdgacbe cefdb cefbgd gcbe
@@ -193,7 +454,8 @@ mod tests {
     #[test]
     fn parses_first_input_line() {
         let input = "be cfbegad cbdgef fgaecd cgeb fdcge agebfd fecdb fabcd edb | fdgacbe cefdb cefbgd gcbe";
-        let input = &parse_input(input).lines[0];
+        let (number, input) = &parse_input(input).lines[0];
+        assert_eq!(1, *number);
         assert_eq!(
             vec![
                 0b0010010, 0b1111111, 0b1111110, 0b1111101, 0b1010110, 0b1111100, 0b1111011,
@@ -211,30 +473,125 @@ mod tests {
     }
 
     #[test]
-    fn deduces_four_digit_value() {
+    fn deduces_four_digit_value() -> anyhow::Result<()> {
         let input =
             "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf";
-        let line = DisplayLine::from(input);
-        assert_eq!(5353, line.deduce_digits());
+        let line = DisplayLine::try_from(input)?;
+        assert_eq!(5353, line.deduce_digits()?);
+        Ok(())
     }
 
     #[test]
     fn count_deduced_digits() {
         let lines = parse_input(INPUT);
-        assert_eq!(61229, lines.count_deduced_digits());
+        let (sum, failures) = lines.count_deduced_digits();
+        assert_eq!(61229, sum);
+        assert!(failures.is_empty());
     }
 
     #[test]
-    fn count_all_deduced_digits() {
+    fn count_all_deduced_digits() -> anyhow::Result<()> {
         let lines = parse_input(INPUT);
         let lines = lines
             .lines
             .iter()
-            .map(|line| line.deduce_digits())
-            .collect_vec();
+            .map(|(_, line)| line.deduce_digits())
+            .collect::<anyhow::Result<Vec<_>>>()?;
         assert_eq!(
             vec![8394, 9781, 1197, 9361, 4873, 8418, 4548, 1625, 8717, 4315,],
             lines,
         );
+        Ok(())
+    }
+
+    #[test]
+    fn deduces_wiring_of_custom_layout() -> anyhow::Result<()> {
+        let layout = SegmentLayout::new(vec![0b0001, 0b0011, 0b0110, 0b1111]);
+        let line = DisplayLine::try_from("abcd c ad ca | ad ca c")?;
+        assert_eq!(
+            Deduction::Solved(vec![1, 3, 0, 2]),
+            line.deduce_wiring(&layout)
+        );
+        assert_eq!(vec![2, 1, 0], line.decode(&layout)?);
+        Ok(())
+    }
+
+    #[test]
+    fn deduces_wiring_of_32_segments() {
+        // every digit lights a different number of segments, from 1 up to all 32
+        let digits = (1..=32).map(|len| u32::MAX >> (32 - len)).collect_vec();
+        let layout = SegmentLayout::new(digits.clone());
+        assert_eq!(32, layout.num_segments);
+        assert_eq!(
+            Deduction::Solved((0..32).collect_vec()),
+            WiringSolver::new(&layout, &digits).solve()
+        );
+    }
+
+    #[test]
+    fn detects_ambiguous_wiring() -> anyhow::Result<()> {
+        let layout = SegmentLayout::seven_segment();
+        let line = DisplayLine::try_from("ab | ab")?;
+        assert_eq!(Deduction::Ambiguous, line.deduce_wiring(&layout));
+        Ok(())
+    }
+
+    #[test]
+    fn detects_inconsistent_wiring() -> anyhow::Result<()> {
+        let layout = SegmentLayout::seven_segment();
+        let line = DisplayLine::try_from("ab cd | ab")?;
+        assert_eq!(Deduction::Inconsistent, line.deduce_wiring(&layout));
+        assert!(line.decode(&layout).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn reports_malformed_notes() {
+        let input = r#"
+            be cfbegad cbdgef fgaecd cgeb fdcge agebfd fecdb fabcd | fdgacbe cefdb cefbgd gcbe
+            be be cbdgef fgaecd cgeb fdcge agebfd fecdb fabcd edb | fdgacbe cefdb cefbgd gcbe
+            be cfbegad cbdgef fgaecd cgeb fdcge agebfd fecdb fabcd edb | fdgacbe cefdb cefbgd gcbh
+            be cfbegad cbdgef fgaecd cgeb fdcge agebfd fecdb fabcd edb | fdgacbe cefdb cefbgd gc3e
+            be cfbegad cbdgef fgaecd cgeb fdcge agebfd fecdb fabcd edb fdgacbe cefdb cefbgd gcbe
+            be cfbegad cbdgef fgaecd cgeb fdcge agebfd fecdb fabcd edb | fdgacbe cefdb cefbgd
+        "#;
+        let notes = parse_input(input);
+        let errors = notes
+            .errors
+            .iter()
+            .map(|error| format!("{:#}", error))
+            .collect_vec();
+        assert_eq!(
+            vec![
+                "Invalid note in line 2: Expected 10 patterns, found 9",
+                "Invalid note in line 3: Duplicate pattern 'be'",
+                "Invalid note in line 4: Invalid segment letter 'h' in 'bcgh'",
+                "Invalid note in line 5: Invalid segment letter '3' in 'gc3e'",
+                "Invalid note in line 6: Missing '|' between patterns and output digits",
+                "Invalid note in line 7: Expected 4 output digits, found 3",
+            ],
+            errors,
+        );
+        assert!(notes.lines.is_empty());
+    }
+
+    #[test]
+    fn sums_only_consistent_lines() {
+        let input = r#"
+            acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf
+            acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ac | cdfeb fcadb cdfeb cdbaf
+        "#;
+        let notes = parse_input(input);
+        assert!(notes.errors.is_empty());
+
+        let (sum, failures) = notes.count_deduced_digits();
+        assert_eq!(5353, sum);
+        assert_eq!(
+            vec!["Failed to deduce line 3: No consistent wiring found"],
+            failures
+                .iter()
+                .map(|error| format!("{:#}", error))
+                .collect_vec(),
+        );
     }
 }
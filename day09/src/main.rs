@@ -1,7 +1,13 @@
-use std::collections::VecDeque;
+use std::{collections::VecDeque, fmt::Display};
 
 use itertools::Itertools;
 
+/// Depth of the walls that separate basins
+const WALL: u8 = 9;
+
+/// ANSI colors used to tell neighboring basins apart
+const COLORS: [u8; 6] = [31, 32, 33, 34, 35, 36];
+
 #[derive(Debug, Clone, PartialEq)]
 struct Point {
     pub x: u32,
@@ -46,38 +52,150 @@ impl HeightMap {
     /// * a basin is surrounded by `9` (wall)
     ///
     pub fn find_basins(&self) -> Vec<usize> {
-        let low_points = self.find_low_points();
-        let basins = low_points
+        self.label_basins()
+            .basins
             .iter()
-            .map(|point| self.find_basin(point.x, point.y))
-            .collect_vec();
-
-        basins
+            .map(|basin| basin.size())
+            .collect_vec()
     }
 
-    /// Return the number of fields that belong to the basin of the low point
-    /// Use breadth search first for now, should be simple enough, maybe not too fast
-    pub fn find_basin(&self, x: u32, y: u32) -> usize {
-        let mut visited: Vec<&Point> = Vec::new();
-        let mut points: VecDeque<&Point> = VecDeque::new();
+    /// Assigns every field that is not a wall to its basin.
+    ///
+    /// Uses a single pass over the map, each field is joined with its left and upper neighbor
+    /// via union-find, afterwards every root is given a basin id in reading order.
+    pub fn label_basins(&self) -> BasinMap {
+        let mut sets = UnionFind::new(self.points.len());
+        for point in self.points.iter().filter(|point| point.depth < WALL) {
+            let index = self.index(point.x, point.y);
+            if point.x > 0 && self.get_point(point.x - 1, point.y).depth < WALL {
+                sets.union(index, index - 1);
+            }
+            if point.y > 0 && self.get_point(point.x, point.y - 1).depth < WALL {
+                sets.union(index, index - self.width as usize);
+            }
+        }
+
+        let mut labels = vec![None; self.points.len()];
+        let mut basins: Vec<Basin> = Vec::new();
+        let mut ids = vec![None; self.points.len()];
+        for (index, point) in self.points.iter().enumerate() {
+            if point.depth >= WALL {
+                continue;
+            }
+            let root = sets.find(index);
+            let id = *ids[root].get_or_insert_with(|| {
+                basins.push(Basin::new(basins.len(), point.clone()));
+                basins.len() - 1
+            });
+            labels[index] = Some(id);
+
+            let basin = &mut basins[id];
+            if point.depth < basin.low_point.depth {
+                basin.low_point = point.clone();
+            }
+            basin.cells.push(point.clone());
+        }
+
+        for basin in basins.iter_mut() {
+            basin.boundary = basin
+                .cells
+                .iter()
+                .flat_map(|point| self.neighbors(point.x, point.y).flatten())
+                .filter(|point| point.depth >= WALL)
+                .unique_by(|point| (point.x, point.y))
+                .cloned()
+                .sorted_by_key(|point| (point.y, point.x))
+                .collect_vec();
+        }
 
-        points.push_back(self.get_point(x, y));
-        visited.push(self.get_point(x, y));
+        BasinMap {
+            width: self.width,
+            points: self.points.clone(),
+            labels,
+            basins,
+        }
+    }
 
-        // for each visited point check if there are more neighbors not visited yet
-        while let Some(point) = points.pop_front() {
-            // append all neighbors that were not already visited and are below depth 9
-            self.neighbors(point.x, point.y).for_each(|point| {
-                if let Some(point) = point {
-                    if !visited.contains(&point) && point.depth < 9 {
-                        points.push_back(&point);
-                        visited.push(point);
+    /// Simulates how water flows over the whole map, walls included.
+    ///
+    /// Every field drains into its lowest neighbor if that is strictly lower than the field itself.
+    /// When several neighbors share the lowest depth the first one in the order left, right, up,
+    /// down is picked.
+    ///
+    /// Fields on a flat plateau drain along the shortest way across the plateau to a field that
+    /// has a lower neighbor. A plateau without any lower neighbor is a flat low point, its first
+    /// field in reading order becomes the sink the other fields drain into.
+    pub fn simulate_flow(&self) -> FlowMap {
+        let mut downstream = self
+            .points
+            .iter()
+            .map(|point| {
+                self.neighbors(point.x, point.y)
+                    .flatten()
+                    .min_by_key(|neighbor| neighbor.depth)
+                    .filter(|neighbor| neighbor.depth < point.depth)
+                    .map(|neighbor| self.index(neighbor.x, neighbor.y))
+            })
+            .collect_vec();
+
+        // routes plateaus via BFS, starting at all fields that drain downhill, then at the
+        // first field of every plateau that has no way down
+        let mut distance = vec![usize::MAX; self.points.len()];
+        let mut queue = VecDeque::new();
+        for (index, next) in downstream.iter().enumerate() {
+            if next.is_some() {
+                distance[index] = 0;
+                queue.push_back(index);
+            }
+        }
+        let mut unrouted = 0..self.points.len();
+        loop {
+            while let Some(index) = queue.pop_front() {
+                let point = &self.points[index];
+                for neighbor in self.neighbors(point.x, point.y).flatten() {
+                    let next = self.index(neighbor.x, neighbor.y);
+                    if neighbor.depth == point.depth && distance[next] == usize::MAX {
+                        distance[next] = distance[index] + 1;
+                        downstream[next] = Some(index);
+                        queue.push_back(next);
                     }
                 }
-            })
+            }
+
+            // the first field of a plateau without a way down becomes its sink
+            let Some(start) = unrouted.find(|&index| distance[index] == usize::MAX) else {
+                break;
+            };
+            distance[start] = 0;
+            queue.push_back(start);
         }
 
-        visited.len()
+        // water only flows downhill or across plateaus towards their exit, visiting fields
+        // from high to low and far to near passes all upstream fields first
+        let order = (0..self.points.len())
+            .sorted_by_key(|&index| (self.points[index].depth, distance[index], index))
+            .collect_vec();
+
+        let mut accumulation = vec![1; self.points.len()];
+        for &index in order.iter().rev() {
+            if let Some(next) = downstream[index] {
+                accumulation[next] += accumulation[index];
+            }
+        }
+
+        let mut sinks = (0..self.points.len()).collect_vec();
+        for &index in order.iter() {
+            if let Some(next) = downstream[index] {
+                sinks[index] = sinks[next];
+            }
+        }
+
+        FlowMap {
+            width: self.width,
+            downstream,
+            accumulation,
+            sinks,
+        }
     }
 
     /// Find all low points in the height map
@@ -89,14 +207,7 @@ impl HeightMap {
             for x in 0..self.width {
                 let depth = self.get_point(x, y).depth;
 
-                // TODO try to refactor filter / map combo
-                let neighbors = self
-                    .neighbors(x, y)
-                    .filter(Option::is_some)
-                    .map(|v| v.unwrap())
-                    .collect_vec();
-
-                if neighbors.iter().all(|&p| p.depth > depth) {
+                if self.neighbors(x, y).flatten().all(|p| p.depth > depth) {
                     result.push(self.get_point(x, y).clone());
                 }
             }
@@ -107,9 +218,13 @@ impl HeightMap {
 
     /// Returns the point at coordinates
     fn get_point(&self, x: u32, y: u32) -> &Point {
+        &self.points[self.index(x, y)]
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
         assert!(x < self.width);
         assert!(y < self.height);
-        &self.points[(y * self.width + x) as usize]
+        (y * self.width + x) as usize
     }
 
     fn neighbors(&self, x: u32, y: u32) -> impl Iterator<Item = Option<&Point>> + '_ {
@@ -127,6 +242,181 @@ impl HeightMap {
     }
 }
 
+/// Disjoint sets of fields, used to merge neighboring fields into basins.
+struct UnionFind {
+    parents: Vec<usize>,
+    ranks: Vec<u8>,
+}
+
+impl UnionFind {
+    pub fn new(size: usize) -> Self {
+        Self {
+            parents: (0..size).collect_vec(),
+            ranks: vec![0; size],
+        }
+    }
+
+    pub fn find(&mut self, index: usize) -> usize {
+        let mut root = index;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+        // compress the path to the root
+        let mut index = index;
+        while self.parents[index] != root {
+            index = std::mem::replace(&mut self.parents[index], root);
+        }
+        root
+    }
+
+    pub fn union(&mut self, lhs: usize, rhs: usize) {
+        let (lhs, rhs) = (self.find(lhs), self.find(rhs));
+        if lhs == rhs {
+            return;
+        }
+        match self.ranks[lhs].cmp(&self.ranks[rhs]) {
+            std::cmp::Ordering::Less => self.parents[lhs] = rhs,
+            std::cmp::Ordering::Greater => self.parents[rhs] = lhs,
+            std::cmp::Ordering::Equal => {
+                self.parents[rhs] = lhs;
+                self.ranks[lhs] += 1;
+            }
+        }
+    }
+}
+
+/// A single basin, all connected fields below the wall height.
+#[derive(Debug)]
+struct Basin {
+    pub id: usize,
+    /// All fields of the basin in reading order
+    pub cells: Vec<Point>,
+    /// The lowest field of the basin
+    pub low_point: Point,
+    /// The wall fields surrounding the basin
+    pub boundary: Vec<Point>,
+}
+
+impl Basin {
+    fn new(id: usize, low_point: Point) -> Self {
+        Self {
+            id,
+            cells: Vec::new(),
+            low_point,
+            boundary: Vec::new(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.cells.len()
+    }
+}
+
+/// The height map where every field is labeled with the id of its basin.
+#[derive(Debug)]
+struct BasinMap {
+    pub width: u32,
+    pub points: Vec<Point>,
+    /// The basin id per field, `None` for walls
+    pub labels: Vec<Option<usize>>,
+    pub basins: Vec<Basin>,
+}
+
+impl BasinMap {
+    /// Returns the basin the given field belongs to
+    pub fn basin_at(&self, x: u32, y: u32) -> Option<&Basin> {
+        if x >= self.width {
+            return None;
+        }
+        let index = (y * self.width + x) as usize;
+        self.labels
+            .get(index)
+            .copied()
+            .flatten()
+            .map(|id| &self.basins[id])
+    }
+}
+
+/// Renders the map, each basin in its own color, walls are left uncolored.
+impl Display for BasinMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (points, labels) in self
+            .points
+            .chunks(self.width as usize)
+            .zip(self.labels.chunks(self.width as usize))
+        {
+            for (point, label) in points.iter().zip(labels) {
+                match label {
+                    Some(id) => {
+                        let color = COLORS[id % COLORS.len()];
+                        write!(f, "\x1b[{}m{}\x1b[0m", color, point.depth)?
+                    }
+                    None => write!(f, "{}", point.depth)?,
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// The result of the flow simulation, every field refers to others by its index.
+#[derive(Debug)]
+struct FlowMap {
+    pub width: u32,
+    /// The neighbor each field drains into, `None` for sinks
+    pub downstream: Vec<Option<usize>>,
+    /// Number of fields draining through each field, including itself
+    pub accumulation: Vec<usize>,
+    /// The sink each field ultimately drains into
+    pub sinks: Vec<usize>,
+}
+
+impl FlowMap {
+    fn position(&self, index: usize) -> (u32, u32) {
+        (index as u32 % self.width, index as u32 / self.width)
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    /// Returns the neighbor the given field drains into
+    pub fn downstream_of(&self, x: u32, y: u32) -> Option<(u32, u32)> {
+        self.downstream[self.index(x, y)].map(|index| self.position(index))
+    }
+
+    /// Returns the number of fields that drain through the given field
+    pub fn accumulation_at(&self, x: u32, y: u32) -> usize {
+        self.accumulation[self.index(x, y)]
+    }
+
+    /// Returns all fields the water passes from the given field down to its sink
+    pub fn flow_path(&self, x: u32, y: u32) -> Vec<(u32, u32)> {
+        let mut path = vec![(x, y)];
+        while let Some(next) = path.last().and_then(|&(x, y)| self.downstream_of(x, y)) {
+            path.push(next);
+        }
+        path
+    }
+
+    /// Returns the sink the given field ultimately drains into
+    pub fn sink_of(&self, x: u32, y: u32) -> (u32, u32) {
+        self.position(self.sinks[self.index(x, y)])
+    }
+
+    /// Returns all sinks with the number of fields draining into them, ordered by position
+    pub fn drainage_areas(&self) -> Vec<((u32, u32), usize)> {
+        self.sinks
+            .iter()
+            .counts()
+            .into_iter()
+            .sorted()
+            .map(|(&sink, count)| (self.position(sink), count))
+            .collect_vec()
+    }
+}
+
 fn parse_input(input: &str) -> HeightMap {
     let lines = input
         .lines()
@@ -144,7 +434,7 @@ fn parse_input(input: &str) -> HeightMap {
     let values = lines
         .iter()
         .flat_map(|&line| line.chars())
-        .map(|val| (val.to_string()).parse::<u8>().unwrap())
+        .map(|val| val.to_digit(10).expect("Failed to parse depth") as u8)
         .collect_vec();
 
     HeightMap::new(width as u32, height as u32, values)
@@ -157,19 +447,50 @@ fn main() {
     let risk_level = low_points.iter().map(|p| p.depth as u32 + 1).sum::<u32>();
     dbg!(risk_level);
 
+    let basin_map = height_map.label_basins();
+    println!("{}", basin_map);
+
+    for point in low_points.iter().take(3) {
+        if let Some(basin) = basin_map.basin_at(point.x, point.y) {
+            println!(
+                "Basin {} at {:?}: size {}, boundary {}",
+                basin.id,
+                (point.x, point.y),
+                basin.size(),
+                basin.boundary.len()
+            );
+        }
+    }
+
+    let flow_map = height_map.simulate_flow();
+    if let Some((sink, area)) = flow_map
+        .drainage_areas()
+        .into_iter()
+        .max_by_key(|&(_, area)| area)
+    {
+        println!("Largest drainage area: {} fields into {:?}", area, sink);
+        println!(
+            "Accumulation at sink: {}",
+            flow_map.accumulation_at(sink.0, sink.1)
+        );
+    }
+    println!(
+        "Top left corner drains via {:?} into {:?}",
+        flow_map.flow_path(0, 0),
+        flow_map.sink_of(0, 0)
+    );
+
     let mut basins = height_map.find_basins();
     basins.sort();
-    let basins = basins
-        .iter()
-        .rev()
-        .take(3)
-        .fold(1, |product, &value| product * value);
+    let basins = basins.iter().rev().take(3).product::<usize>();
 
     println!("BASINS: {:?}", basins);
 }
 
 #[cfg(test)]
 mod tests {
+    use itertools::Itertools;
+
     use crate::{parse_input, Point};
 
     const INPUT: &str = r#"
@@ -198,10 +519,128 @@ mod tests {
 
     #[test]
     fn find_basin_of_low_point() {
+        let basin_map = parse_input(INPUT).label_basins();
+        let find_basin = |x, y| basin_map.basin_at(x, y).map(|basin| basin.size());
+        assert_eq!(Some(3), find_basin(1, 0));
+        assert_eq!(Some(9), find_basin(9, 0));
+        assert_eq!(Some(14), find_basin(2, 2));
+        assert_eq!(Some(9), find_basin(6, 4));
+        assert_eq!(None, find_basin(2, 0));
+    }
+
+    #[test]
+    fn label_basins_of_height_map() {
         let height_map = parse_input(INPUT);
-        assert_eq!(3, height_map.find_basin(1, 0));
-        assert_eq!(9, height_map.find_basin(9, 0));
-        assert_eq!(14, height_map.find_basin(2, 2));
-        assert_eq!(9, height_map.find_basin(6, 4));
+        let basin_map = height_map.label_basins();
+        assert_eq!(4, basin_map.basins.len());
+        assert_eq!(
+            vec![3, 9, 14, 9],
+            basin_map
+                .basins
+                .iter()
+                .map(|basin| basin.size())
+                .collect_vec(),
+        );
+        assert_eq!(
+            vec![
+                Point::new(1, 0, 1),
+                Point::new(9, 0, 0),
+                Point::new(2, 2, 5),
+                Point::new(6, 4, 5),
+            ],
+            basin_map
+                .basins
+                .iter()
+                .map(|basin| basin.low_point.clone())
+                .collect_vec(),
+        );
+        assert_eq!(None, basin_map.labels[2]);
+        assert_eq!(Some(0), basin_map.labels[0]);
+        assert_eq!(Some(1), basin_map.basin_at(5, 0).map(|basin| basin.id));
+    }
+
+    #[test]
+    fn find_boundary_of_basin() {
+        let height_map = parse_input(INPUT);
+        let basin_map = height_map.label_basins();
+        assert_eq!(
+            vec![
+                Point::new(2, 0, 9),
+                Point::new(1, 1, 9),
+                Point::new(0, 2, 9),
+            ],
+            basin_map.basins[0].boundary,
+        );
+    }
+
+    #[test]
+    fn simulate_water_flow() {
+        let flow_map = parse_input(INPUT).simulate_flow();
+        assert_eq!(Some((0, 0)), flow_map.downstream_of(0, 1));
+        assert_eq!(Some((1, 0)), flow_map.downstream_of(0, 0));
+        assert_eq!(None, flow_map.downstream_of(1, 0));
+        assert_eq!((1, 0), flow_map.sink_of(0, 1));
+        assert_eq!(2, flow_map.accumulation_at(0, 1));
+        assert_eq!(3, flow_map.accumulation_at(0, 0));
+        assert_eq!(
+            vec![(0, 2), (0, 1), (0, 0), (1, 0)],
+            flow_map.flow_path(0, 2)
+        );
+    }
+
+    #[test]
+    fn drain_every_field_into_low_points() {
+        let height_map = parse_input(INPUT);
+        let flow_map = height_map.simulate_flow();
+        let areas = flow_map.drainage_areas();
+
+        let low_points = height_map
+            .find_low_points()
+            .iter()
+            .map(|point| (point.x, point.y))
+            .sorted_by_key(|&(x, y)| (y, x))
+            .collect_vec();
+        assert_eq!(
+            low_points,
+            areas.iter().map(|&(sink, _)| sink).collect_vec()
+        );
+        assert_eq!(50, areas.iter().map(|&(_, area)| area).sum::<usize>());
+        for &(x, y) in low_points.iter() {
+            let area = areas.iter().find(|&&(sink, _)| sink == (x, y)).unwrap().1;
+            assert_eq!(area, flow_map.accumulation_at(x, y));
+        }
+    }
+
+    #[test]
+    fn break_flow_ties_by_neighbor_order() {
+        let flow_map = parse_input("010").simulate_flow();
+        assert_eq!(Some((0, 0)), flow_map.downstream_of(1, 0));
+        assert_eq!(vec![((0, 0), 2), ((2, 0), 1)], flow_map.drainage_areas());
+    }
+
+    #[test]
+    fn drain_plateaus_into_low_points() {
+        let flow_map = parse_input("11").simulate_flow();
+        assert_eq!(Some((0, 0)), flow_map.downstream_of(1, 0));
+        assert_eq!(None, flow_map.downstream_of(0, 0));
+        assert_eq!(2, flow_map.accumulation_at(0, 0));
+        assert_eq!(vec![((0, 0), 2)], flow_map.drainage_areas());
+
+        let flow_map = parse_input("212").simulate_flow();
+        assert_eq!(Some((1, 0)), flow_map.downstream_of(0, 0));
+        assert_eq!(Some((1, 0)), flow_map.downstream_of(2, 0));
+        assert_eq!(vec![((1, 0), 3)], flow_map.drainage_areas());
+
+        // the plateau of 2s drains across to its exit, the plateau of 1s into its first field
+        let flow_map = parse_input("22211\n22299").simulate_flow();
+        assert_eq!(
+            vec![(0, 1), (0, 0), (1, 0), (2, 0), (3, 0)],
+            flow_map.flow_path(0, 1)
+        );
+        assert_eq!(Some((3, 0)), flow_map.downstream_of(4, 0));
+        assert_eq!(Some((3, 0)), flow_map.downstream_of(3, 1));
+        assert_eq!(vec![((3, 0), 10)], flow_map.drainage_areas());
+        assert_eq!(10, flow_map.accumulation_at(3, 0));
+        assert_eq!(6, flow_map.accumulation_at(2, 0));
     }
 }
@@ -1,17 +1,15 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+    fmt::Display,
+};
 
 use itertools::Itertools;
 
-#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
 pub enum Token {
-    LeftParen,
-    RightParen,
-    LeftBrace,
-    RightBrace,
-    LeftArrow,
-    RightArrow,
-    LeftBracket,
-    RightBracket,
+    Open(char),
+    Close(char),
 }
 
 impl Display for Token {
@@ -20,66 +18,98 @@ impl Display for Token {
     }
 }
 
-impl From<char> for Token {
-    fn from(val: char) -> Self {
-        match val {
-            '(' => Token::LeftParen,
-            ')' => Token::RightParen,
-            '{' => Token::LeftBrace,
-            '}' => Token::RightBrace,
-            '<' => Token::LeftArrow,
-            '>' => Token::RightArrow,
-            '[' => Token::LeftBracket,
-            ']' => Token::RightBracket,
-            v => panic!("Unknown char '{}' found", v),
+impl Token {
+    pub fn opens(&self) -> bool {
+        matches!(self, Token::Open(_))
+    }
+
+    pub fn as_char(&self) -> char {
+        match self {
+            Token::Open(c) | Token::Close(c) => *c,
         }
     }
 }
 
-impl Token {
-    pub fn opens(&self) -> bool {
-        const TOKENS: [Token; 4] = [
-            Token::LeftParen,
-            Token::LeftBrace,
-            Token::LeftArrow,
-            Token::LeftBracket,
-        ];
-        TOKENS.contains(self)
+/// The set of delimiter pairs a chunk is made of.
+///
+/// A pair may use the same char to open and close, e.g. quotes, such a char closes
+/// the innermost chunk if it was opened by it, otherwise it opens a new chunk.
+#[derive(Debug, Clone)]
+pub struct Grammar {
+    pairs: Vec<(char, char)>,
+}
+
+impl Default for Grammar {
+    fn default() -> Self {
+        Self::new(&[('(', ')'), ('[', ']'), ('{', '}'), ('<', '>')])
     }
+}
 
-    pub fn matches(&self, rhs: &Token) -> bool {
-        matches!(
-            (self, rhs),
-            (Token::LeftParen, Token::RightParen)
-                | (Token::LeftBrace, Token::RightBrace)
-                | (Token::LeftArrow, Token::RightArrow)
-                | (Token::LeftBracket, Token::RightBracket)
-        )
+impl Grammar {
+    pub fn new(pairs: &[(char, char)]) -> Self {
+        Self {
+            pairs: pairs.to_vec(),
+        }
     }
 
-    pub fn opposite(&self) -> Token {
-        match self {
-            Token::LeftParen => Token::RightParen,
-            Token::RightParen => Token::LeftParen,
-            Token::LeftBrace => Token::RightBrace,
-            Token::RightBrace => Token::LeftBrace,
-            Token::LeftArrow => Token::RightArrow,
-            Token::RightArrow => Token::LeftArrow,
-            Token::LeftBracket => Token::RightBracket,
-            Token::RightBracket => Token::LeftBracket,
+    /// Returns the token for the given char, `None` if the char is not part of the grammar.
+    ///
+    /// The innermost open token is required to tell symmetric delimiters apart.
+    pub fn token(&self, c: char, innermost: Option<&Token>) -> Option<Token> {
+        let opens = self.pairs.iter().any(|&(open, _)| open == c);
+        let closes = self.pairs.iter().any(|&(_, close)| close == c);
+        match (opens, closes) {
+            (true, true) if innermost.map(|token| self.matches(token, c)) == Some(true) => {
+                Some(Token::Close(c))
+            }
+            (true, _) => Some(Token::Open(c)),
+            (false, true) => Some(Token::Close(c)),
+            (false, false) => None,
         }
     }
 
-    pub fn as_char(&self) -> char {
-        match self {
-            Token::LeftParen => '(',
-            Token::RightParen => ')',
-            Token::LeftBrace => '{',
-            Token::RightBrace => '}',
-            Token::LeftArrow => '<',
-            Token::RightArrow => '>',
-            Token::LeftBracket => '[',
-            Token::RightBracket => ']',
+    /// Returns true if the given open token is closed by the char.
+    pub fn matches(&self, token: &Token, close: char) -> bool {
+        self.opposite(token) == Some(close)
+    }
+
+    /// Returns all chars of the grammar, openers first.
+    pub fn chars(&self) -> Vec<char> {
+        self.pairs
+            .iter()
+            .map(|&(open, _)| open)
+            .chain(self.pairs.iter().map(|&(_, close)| close))
+            .unique()
+            .collect_vec()
+    }
+
+    /// Applies the char to the stack of open tokens, returns false if it cannot be applied.
+    pub fn apply(&self, stack: &mut Vec<Token>, c: char) -> bool {
+        match self.token(c, stack.last()) {
+            Some(token) if token.opens() => {
+                stack.push(token);
+                true
+            }
+            Some(_) => match stack.last() {
+                Some(last_token) if self.matches(last_token, c) => {
+                    stack.pop();
+                    true
+                }
+                _ => false,
+            },
+            None => false,
+        }
+    }
+
+    /// Returns the closing char for the given open token.
+    pub fn opposite(&self, token: &Token) -> Option<char> {
+        match token {
+            Token::Open(c) => self
+                .pairs
+                .iter()
+                .find(|&&(open, _)| open == *c)
+                .map(|&(_, close)| close),
+            Token::Close(_) => None,
         }
     }
 }
@@ -87,10 +117,50 @@ impl Token {
 #[derive(Debug, PartialEq, Clone)]
 enum DecoderResult {
     Ok,
-    /// Expected token char and found char
-    Corrupt(char, char),
-    /// Remaining stack as string
-    Incomplete(String),
+    /// Expected token char and found char at `column`, the unmatched opener is at `opener`
+    Corrupt {
+        expected: char,
+        found: char,
+        column: usize,
+        opener: usize,
+    },
+    /// Closing char found without any open chunk and its position
+    Unopened(char, usize),
+    /// Char that is not part of the grammar and its position
+    InvalidChar(char, usize),
+    /// Remaining stack as string, with the position of the opener each missing char closes
+    Incomplete {
+        missing: String,
+        openers: Vec<usize>,
+    },
+}
+
+/// A single change to a line, positions are byte offsets into the original line.
+#[derive(Debug, PartialEq, Clone)]
+enum Edit {
+    /// Replaces the char at the position
+    Replace(usize, char),
+    /// Inserts the char before the position
+    Insert(usize, char),
+    /// Deletes the char at the position
+    Delete(usize),
+}
+
+/// The fix for a line with the fewest edits
+#[derive(Debug, PartialEq, Clone)]
+struct Repair {
+    pub edits: Vec<Edit>,
+    /// The line with all edits applied
+    pub line: String,
+    /// The closing chars to append to complete the repaired line
+    pub completion: String,
+}
+
+impl Repair {
+    /// Scores the completion through the grammar the repair was made with
+    pub fn completion_score(&self, grammar: &Grammar) -> Option<u64> {
+        incomplete_score(&self.completion, grammar)
+    }
 }
 
 fn corrupted_score(lines: &[String]) -> u32 {
@@ -100,30 +170,43 @@ fn corrupted_score(lines: &[String]) -> u32 {
 
     let mut score = 0;
     for line in lines {
-        if let DecoderResult::Corrupt(_expected, found) = decode_chunk(&line) {
+        let first_corrupt = decode_chunk(line)
+            .into_iter()
+            .find(|result| matches!(result, DecoderResult::Corrupt { .. }));
+        if let Some(DecoderResult::Corrupt { found, .. }) = first_corrupt {
             score += score_table[&found];
         }
     }
     score
 }
 
-fn incomplete_score(tokens: &str) -> u64 {
-    let score_table: HashMap<char, u64> = [(')', 1), (']', 2), ('}', 3), ('>', 4)]
-        .into_iter()
+/// Scores the closing chars, each is worth the position of its pair in the grammar plus one,
+/// in base `pairs + 1`. Returns `None` if a char closes no pair or the score overflows.
+fn incomplete_score(tokens: &str, grammar: &Grammar) -> Option<u64> {
+    let score_table: HashMap<char, u64> = grammar
+        .pairs
+        .iter()
+        .enumerate()
+        // a closer shared by several pairs scores as the first of them
+        .rev()
+        .map(|(index, &(_, close))| (close, index as u64 + 1))
         .collect();
+    let base = grammar.pairs.len() as u64 + 1;
 
     let mut total = 0_u64;
     for c in tokens.chars() {
-        total = total * 5 + score_table[&c];
+        total = total
+            .checked_mul(base)?
+            .checked_add(*score_table.get(&c)?)?;
     }
-    total
+    Some(total)
 }
 
 fn score_2nd(lines: &[String]) -> u64 {
     let mut scores: Vec<u64> = Vec::new();
     for line in lines {
-        if let DecoderResult::Incomplete(tokens) = decode_chunk(&line) {
-            scores.push(incomplete_score(&tokens));
+        if let [DecoderResult::Incomplete { missing, .. }] = &decode_chunk(line)[..] {
+            scores.extend(incomplete_score(missing, &Grammar::default()));
         }
     }
 
@@ -136,31 +219,288 @@ fn score_2nd(lines: &[String]) -> u64 {
     scores[index]
 }
 
-/// Decodes the chunk, returns a result representing decode state
-fn decode_chunk(chunk: &str) -> DecoderResult {
-    fn missing_tokens(list: &[Token]) -> String {
-        list.iter().rev().map(|t| t.opposite().as_char()).join("")
+/// Decodes chunks using a grammar of delimiter pairs
+struct Decoder {
+    grammar: Grammar,
+}
+
+impl Decoder {
+    pub fn new(grammar: Grammar) -> Self {
+        Self { grammar }
+    }
+
+    /// Decodes the chunk, returns all errors found in order, or the final decode state.
+    ///
+    /// After a corrupt closing char the decoder recovers by treating it as the closing
+    /// char of the innermost chunk, unknown or unopened chars are skipped.
+    /// All positions are byte offsets into the chunk.
+    pub fn decode(&self, chunk: &str) -> Vec<DecoderResult> {
+        let mut results = Vec::new();
+        let mut stack: Vec<(Token, usize)> = Vec::new();
+
+        for (pos, c) in chunk.char_indices() {
+            match self.grammar.token(c, stack.last().map(|(token, _)| token)) {
+                None => results.push(DecoderResult::InvalidChar(c, pos)),
+                Some(token) if token.opens() => stack.push((token, pos)),
+                Some(_) => match stack.pop() {
+                    Some((last_token, opener)) if !self.grammar.matches(&last_token, c) => {
+                        results.push(DecoderResult::Corrupt {
+                            expected: self.grammar.opposite(&last_token).unwrap_or_default(),
+                            found: c,
+                            column: pos,
+                            opener,
+                        });
+                    }
+                    Some(_) => (),
+                    None => results.push(DecoderResult::Unopened(c, pos)),
+                },
+            }
+        }
+
+        if !stack.is_empty() {
+            results.push(DecoderResult::Incomplete {
+                missing: self.missing_tokens(&stack),
+                openers: stack.iter().rev().map(|&(_, pos)| pos).collect_vec(),
+            });
+        }
+        if results.is_empty() {
+            results.push(DecoderResult::Ok);
+        }
+        results
+    }
+
+    fn missing_tokens(&self, list: &[(Token, usize)]) -> String {
+        list.iter()
+            .rev()
+            .filter_map(|(t, _)| self.grammar.opposite(t))
+            .join("")
     }
+}
+
+impl Decoder {
+    /// Finds the fewest edits that turn the chunk into a valid or incomplete chunk.
+    ///
+    /// Searches states of position and open tokens with Dijkstra, consuming a valid char is free,
+    /// every edit costs 1. Among repairs with the same number of edits, ones that edit later
+    /// in the line are preferred, e.g. a corrupt closing char is replaced by the expected one.
+    /// Returns `None` if more than `max_edits` edits are required.
+    pub fn repair(&self, chunk: &str, max_edits: usize) -> Option<Repair> {
+        let chars = chunk.char_indices().collect_vec();
+        let offset = |index: usize| chars.get(index).map_or(chunk.len(), |&(pos, _)| pos);
+        let candidates = self.grammar.chars();
+
+        // all applied edits, each refers to the previous edit of the same path
+        let mut edits: Vec<(Option<usize>, Edit)> = Vec::new();
+        let mut visited: HashSet<(usize, Vec<Token>)> = HashSet::new();
+        let mut queue = BinaryHeap::new();
+        queue.push(Reverse((0, Reverse(0), 0, Vec::new(), None)));
+
+        let mut counter = 0;
+        let mut push = |queue: &mut BinaryHeap<_>, cost, index, stack, edit| {
+            counter += 1;
+            queue.push(Reverse((cost, Reverse(index), counter, stack, edit)));
+        };
+
+        while let Some(Reverse((cost, Reverse(index), _, stack, last_edit))) = queue.pop() {
+            if !visited.insert((index, stack.clone())) {
+                continue;
+            }
+            if index == chars.len() {
+                return Some(self.build_repair(chunk, &edits, last_edit, &stack));
+            }
 
-    let mut stack = Vec::new();
+            let c = chars[index].1;
+            let mut next = stack.clone();
+            if self.grammar.apply(&mut next, c) {
+                push(&mut queue, cost, index + 1, next, last_edit);
+            }
+            if cost == max_edits {
+                continue;
+            }
 
-    for token in chunk.chars().map(Token::from).collect_vec() {
-        if token.opens() {
-            stack.push(token);
-        } else if let Some(last_token) = stack.pop() {
-            if !last_token.matches(&token) {
-                return DecoderResult::Corrupt(last_token.opposite().as_char(), token.as_char());
+            let mut add_edit = |edit: Edit| {
+                edits.push((last_edit, edit));
+                Some(edits.len() - 1)
+            };
+            // prefer closing the innermost chunk, then dropping the char, then opening a new chunk
+            let replacements = candidates
+                .iter()
+                .filter(|&&candidate| candidate != c)
+                .filter_map(|&candidate| {
+                    let mut next = stack.clone();
+                    self.grammar
+                        .apply(&mut next, candidate)
+                        .then_some((candidate, next))
+                })
+                .collect_vec();
+            for (candidate, next) in replacements
+                .iter()
+                .filter(|(_, next)| next.len() < stack.len())
+            {
+                let edit = add_edit(Edit::Replace(offset(index), *candidate));
+                push(&mut queue, cost + 1, index + 1, next.clone(), edit);
+            }
+            let edit = add_edit(Edit::Delete(offset(index)));
+            push(&mut queue, cost + 1, index + 1, stack.clone(), edit);
+            for (candidate, next) in replacements
+                .iter()
+                .filter(|(_, next)| next.len() > stack.len())
+            {
+                let edit = add_edit(Edit::Replace(offset(index), *candidate));
+                push(&mut queue, cost + 1, index + 1, next.clone(), edit);
+            }
+            for &candidate in candidates.iter() {
+                let mut next = stack.clone();
+                if self.grammar.apply(&mut next, candidate) {
+                    let edit = add_edit(Edit::Insert(offset(index), candidate));
+                    push(&mut queue, cost + 1, index, next, edit);
+                }
             }
         }
+
+        None
     }
 
-    if stack.is_empty() {
-        DecoderResult::Ok
-    } else {
-        DecoderResult::Incomplete(missing_tokens(&stack))
+    fn build_repair(
+        &self,
+        chunk: &str,
+        edits: &[(Option<usize>, Edit)],
+        last_edit: Option<usize>,
+        stack: &[Token],
+    ) -> Repair {
+        let mut path = Vec::new();
+        let mut current = last_edit;
+        while let Some(index) = current {
+            let (previous, edit) = &edits[index];
+            path.push(edit.clone());
+            current = *previous;
+        }
+        path.reverse();
+
+        let mut line = String::new();
+        let mut pending = path.iter().peekable();
+        for (pos, c) in chunk.char_indices().chain([(chunk.len(), '\0')]) {
+            let mut keep = pos < chunk.len();
+            while let Some(edit) = pending.next_if(|edit| match edit {
+                Edit::Replace(at, _) | Edit::Insert(at, _) | Edit::Delete(at) => *at == pos,
+            }) {
+                match edit {
+                    Edit::Insert(_, inserted) => line.push(*inserted),
+                    Edit::Replace(_, replaced) => {
+                        line.push(*replaced);
+                        keep = false;
+                    }
+                    Edit::Delete(_) => keep = false,
+                }
+            }
+            if keep {
+                line.push(c);
+            }
+        }
+
+        Repair {
+            edits: path,
+            line,
+            completion: stack
+                .iter()
+                .rev()
+                .filter_map(|token| self.grammar.opposite(token))
+                .collect(),
+        }
     }
 }
 
+/// Decodes the chunk with the default grammar, returns a result representing decode state
+fn decode_chunk(chunk: &str) -> Vec<DecoderResult> {
+    Decoder::new(Grammar::default()).decode(chunk)
+}
+
+/// Returns the column to print a marker at for the given byte offset
+fn column_of(line: &str, pos: usize) -> usize {
+    line[..pos].chars().count()
+}
+
+/// Renders a single result in the style of rustc, with markers below the offending chars.
+///
+/// The line number is 1-based, as are the columns in the printed location.
+fn render_diagnostic(result: &DecoderResult, number: usize, line: &str) -> Option<String> {
+    let (message, column, label, openers) = match result {
+        DecoderResult::Ok => return None,
+        DecoderResult::Corrupt {
+            expected,
+            found,
+            column,
+            opener,
+        } => (
+            format!("mismatched closing delimiter `{}`", found),
+            *column,
+            format!("expected `{}`", expected),
+            vec![*opener],
+        ),
+        DecoderResult::Unopened(found, column) => (
+            format!("unexpected closing delimiter `{}`", found),
+            *column,
+            "no open chunk".to_string(),
+            vec![],
+        ),
+        DecoderResult::InvalidChar(found, column) => (
+            format!("invalid character `{}`", found),
+            *column,
+            "not part of the grammar".to_string(),
+            vec![],
+        ),
+        DecoderResult::Incomplete { missing, openers } => (
+            "incomplete chunk".to_string(),
+            line.len(),
+            format!("missing `{}`", missing),
+            openers.clone(),
+        ),
+    };
+
+    let gutter = " ".repeat(number.to_string().len());
+    let caret = column_of(line, column);
+    let openers = openers
+        .iter()
+        .map(|&pos| column_of(line, pos))
+        .sorted()
+        .collect_vec();
+
+    let mut markers = (0..caret)
+        .map(|col| if openers.contains(&col) { '-' } else { ' ' })
+        .collect::<String>();
+    markers.push('^');
+
+    let mut output = vec![
+        format!("error: {}", message),
+        format!("{}--> {}:{}", gutter, number, caret + 1),
+        format!("{} |", gutter),
+        format!("{} | {}", number, line),
+        format!("{} | {} {}", gutter, markers, label),
+    ];
+    // a single opener is labeled like the unclosed delimiter in rustc
+    if let [opener] = openers[..] {
+        let indent = " ".repeat(opener);
+        let opener = line[..].chars().nth(opener).unwrap_or_default();
+        output.push(format!("{} | {}|", gutter, indent));
+        output.push(format!("{} | {}unclosed `{}`", gutter, indent, opener));
+    }
+    Some(output.join("\n"))
+}
+
+/// Renders the diagnostics of all lines, lines without errors are left out.
+fn render_diagnostics(lines: &[String]) -> String {
+    lines
+        .iter()
+        .enumerate()
+        .flat_map(|(index, line)| {
+            decode_chunk(line)
+                .iter()
+                .filter_map(|result| render_diagnostic(result, index + 1, line))
+                .collect_vec()
+        })
+        .join("\n\n")
+}
+
 fn parse_input(input: &str) -> Vec<String> {
     input
         .lines()
@@ -173,17 +513,37 @@ fn parse_input(input: &str) -> Vec<String> {
 fn main() {
     let chunks = parse_input(include_str!("input.txt"));
 
+    println!("{}", render_diagnostics(&chunks));
+
     let total = corrupted_score(&chunks);
     dbg!(total);
 
     let total = score_2nd(&chunks);
     assert!(total < 392811529719);
     dbg!(total);
+
+    let decoder = Decoder::new(Grammar::default());
+    for line in chunks.iter() {
+        if let Some(repair) = decoder.repair(line, 2) {
+            println!(
+                "{} => {}{} (score {})",
+                line,
+                repair.line,
+                repair.completion,
+                repair
+                    .completion_score(&decoder.grammar)
+                    .unwrap_or_default()
+            );
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{DecoderResult, corrupted_score, decode_chunk, incomplete_score, parse_input, score_2nd};
+    use crate::{
+        corrupted_score, decode_chunk, incomplete_score, parse_input, render_diagnostics,
+        score_2nd, Decoder, DecoderResult, Edit, Grammar,
+    };
 
     const INPUT: &str = r#"
         [({(<(())[]>[[{[]{<()<>>
@@ -198,23 +558,42 @@ mod tests {
         <{([{{}}[<[[[<>{}]]]>[]]
     "#;
 
+    fn corrupt(expected: char, found: char, column: usize, opener: usize) -> DecoderResult {
+        DecoderResult::Corrupt {
+            expected,
+            found,
+            column,
+            opener,
+        }
+    }
+
+    fn incomplete(missing: &str, openers: &[usize]) -> DecoderResult {
+        DecoderResult::Incomplete {
+            missing: missing.to_string(),
+            openers: openers.to_vec(),
+        }
+    }
+
     #[test]
     fn test_valid_lines() {
-        assert_eq!(DecoderResult::Ok, decode_chunk("[]"));
-        assert_eq!(DecoderResult::Ok, decode_chunk("([])"));
-        assert_eq!(DecoderResult::Ok, decode_chunk("{()()()}"));
-        assert_eq!(DecoderResult::Ok, decode_chunk("<([{}])>"));
-        assert_eq!(DecoderResult::Ok, decode_chunk("[<>({}){}[([])<>]]"));
-        assert_eq!(DecoderResult::Ok, decode_chunk("(((((((((())))))))))"));
+        assert_eq!(vec![DecoderResult::Ok], decode_chunk("[]"));
+        assert_eq!(vec![DecoderResult::Ok], decode_chunk("([])"));
+        assert_eq!(vec![DecoderResult::Ok], decode_chunk("{()()()}"));
+        assert_eq!(vec![DecoderResult::Ok], decode_chunk("<([{}])>"));
+        assert_eq!(vec![DecoderResult::Ok], decode_chunk("[<>({}){}[([])<>]]"));
+        assert_eq!(
+            vec![DecoderResult::Ok],
+            decode_chunk("(((((((((())))))))))")
+        );
     }
 
     #[test]
     fn test_short_corrupted_lines() {
-        assert_eq!(DecoderResult::Corrupt(')', ']'), decode_chunk("(]"));
-        assert_eq!(DecoderResult::Corrupt('}', '>'), decode_chunk("{()()()>"));
-        assert_eq!(DecoderResult::Corrupt(')', '}'), decode_chunk("(((()))}"));
+        assert_eq!(vec![corrupt(')', ']', 1, 0)], decode_chunk("(]"));
+        assert_eq!(vec![corrupt('}', '>', 7, 0)], decode_chunk("{()()()>"));
+        assert_eq!(vec![corrupt(')', '}', 7, 0)], decode_chunk("(((()))}"));
         assert_eq!(
-            DecoderResult::Corrupt('>', ')'),
+            vec![corrupt('>', ')', 13, 0)],
             decode_chunk("<([]){()}[{}])")
         );
     }
@@ -222,23 +601,38 @@ mod tests {
     #[test]
     fn test_corrupted_lines() {
         assert_eq!(
-            DecoderResult::Corrupt(']', '}'),
+            vec![
+                corrupt(']', '}', 12, 7),
+                incomplete(")]}})])}", &[19, 18, 17, 14, 3, 2, 1, 0]),
+            ],
             decode_chunk("{([(<{}[<>[]}>{[]{[(<()>")
         );
         assert_eq!(
-            DecoderResult::Corrupt(']', ')'),
+            vec![
+                corrupt(']', ')', 8, 3),
+                incomplete("])>>]]", &[11, 10, 9, 2, 1, 0]),
+            ],
             decode_chunk("[[<[([]))<([[{}[[()]]]")
         );
         assert_eq!(
-            DecoderResult::Corrupt(')', ']'),
+            vec![
+                corrupt(')', ']', 7, 4),
+                incomplete(")}]}])]}]", &[20, 15, 14, 13, 12, 11, 2, 1, 0]),
+            ],
             decode_chunk("[{[{({}]{}}([{[{{{}}([]")
         );
         assert_eq!(
-            DecoderResult::Corrupt('>', ')'),
+            vec![
+                corrupt('>', ')', 10, 7),
+                incomplete("))>)>)>]", &[17, 14, 13, 4, 3, 2, 1, 0]),
+            ],
             decode_chunk("[<(<(<(<{}))><([]([]()")
         );
         assert_eq!(
-            DecoderResult::Corrupt(']', '>'),
+            vec![
+                corrupt(']', '>', 16, 5),
+                incomplete("}}>>))])}>", &[21, 20, 19, 18, 17, 4, 3, 2, 1, 0]),
+            ],
             decode_chunk("<{([([[(<>()){}]>(<<{{")
         );
     }
@@ -246,19 +640,19 @@ mod tests {
     #[test]
     fn test_incomplete_lines() {
         assert_eq!(
-            DecoderResult::Incomplete("}}]])})]".to_string()),
+            vec![incomplete("}}]])})]", &[17, 14, 13, 12, 3, 2, 1, 0])],
             decode_chunk("[({(<(())[]>[[{[]{<()<>>"),
         );
         assert_eq!(
-            DecoderResult::Incomplete(")}>]})".to_string()),
+            vec![incomplete(")}>]})", &[21, 14, 13, 12, 11, 10])],
             decode_chunk("[(()[<>])]({[<{<<[]>>("),
         );
         assert_eq!(
-            DecoderResult::Incomplete("}}>}>))))".to_string()),
+            vec![incomplete("}}>}>))))", &[18, 15, 10, 9, 8, 3, 2, 1, 0])],
             decode_chunk("(((({<>}<{<{<>}{[]{[]{}"),
         );
         assert_eq!(
-            DecoderResult::Incomplete("]]}}]}]}>".to_string()),
+            vec![incomplete("]]}}]}]}>", &[20, 19, 16, 13, 12, 11, 10, 9, 8])],
             decode_chunk("{<[[]]>}<{[{[{[]{()[[[]"),
         );
     }
@@ -271,11 +665,12 @@ mod tests {
 
     #[test]
     fn test_incomplete_scores() {
-        assert_eq!(288957, incomplete_score("}}]])})]"));
-        assert_eq!(5566, incomplete_score(")}>]})"));
-        assert_eq!(1480781, incomplete_score("}}>}>))))"));
-        assert_eq!(995444, incomplete_score("]]}}]}]}>"));
-        assert_eq!(294, incomplete_score("])}>"));
+        let grammar = Grammar::default();
+        assert_eq!(Some(288957), incomplete_score("}}]])})]", &grammar));
+        assert_eq!(Some(5566), incomplete_score(")}>]})", &grammar));
+        assert_eq!(Some(1480781), incomplete_score("}}>}>))))", &grammar));
+        assert_eq!(Some(995444), incomplete_score("]]}}]}]}>", &grammar));
+        assert_eq!(Some(294), incomplete_score("])}>", &grammar));
     }
 
     #[test]
@@ -283,4 +678,125 @@ mod tests {
         let input = parse_input(INPUT);
         assert_eq!(288957, score_2nd(&input));
     }
+
+    #[test]
+    fn test_reports_all_errors_in_line() {
+        assert_eq!(
+            vec![
+                corrupt(')', ']', 1, 0),
+                corrupt('}', '>', 3, 2),
+                DecoderResult::Unopened(')', 4),
+            ],
+            decode_chunk("(]{>)"),
+        );
+    }
+
+    #[test]
+    fn test_invalid_chars() {
+        assert_eq!(
+            vec![
+                DecoderResult::InvalidChar('a', 1),
+                DecoderResult::InvalidChar('?', 4),
+            ],
+            decode_chunk("(a[]?)"),
+        );
+    }
+
+    #[test]
+    fn test_custom_grammar() {
+        let decoder = Decoder::new(Grammar::new(&[('(', ')'), ('"', '"'), ('|', '|')]));
+        assert_eq!(vec![DecoderResult::Ok], decoder.decode(r#"("()"|""|)"#));
+        assert_eq!(
+            vec![incomplete(r#")")"#, &[2, 1, 0])],
+            decoder.decode(r#"("("#)
+        );
+        assert_eq!(
+            vec![corrupt('"', ')', 2, 1), incomplete(")", &[0]),],
+            decoder.decode(r#"(")"#)
+        );
+        assert_eq!(
+            vec![DecoderResult::InvalidChar('[', 1)],
+            decoder.decode("([)")
+        );
+    }
+
+    #[test]
+    fn test_render_corrupt_diagnostic() {
+        let lines = parse_input("[<>({}){}[([])<>]]\n{()()()>");
+        let expected = [
+            "error: mismatched closing delimiter `>`",
+            " --> 2:8",
+            "  |",
+            "2 | {()()()>",
+            "  | -      ^ expected `}`",
+            "  | |",
+            "  | unclosed `{`",
+        ];
+        assert_eq!(expected.join("\n"), render_diagnostics(&lines));
+    }
+
+    #[test]
+    fn test_render_incomplete_diagnostic() {
+        let lines = parse_input("[(()[<>])]({[<{<<[]>>(");
+        let expected = [
+            "error: incomplete chunk",
+            " --> 1:23",
+            "  |",
+            "1 | [(()[<>])]({[<{<<[]>>(",
+            "  |           -----      -^ missing `)}>]})`",
+        ];
+        assert_eq!(expected.join("\n"), render_diagnostics(&lines));
+    }
+
+    #[test]
+    fn test_repair_corrupted_lines() {
+        let decoder = Decoder::new(Grammar::default());
+        let repair = decoder.repair("{()()()>", 2).unwrap();
+        assert_eq!(vec![Edit::Replace(7, '}')], repair.edits);
+        assert_eq!("{()()()}", repair.line);
+        assert_eq!("", repair.completion);
+
+        let repair = decoder.repair("{([(<{}[<>[]}>{[]{[(<()>", 2).unwrap();
+        assert_eq!(vec![Edit::Replace(12, ']')], repair.edits);
+        assert_eq!("{([(<{}[<>[]]>{[]{[(<()>", repair.line);
+        assert_eq!(")]}})])}", repair.completion);
+        assert_eq!(Some(120808), repair.completion_score(&decoder.grammar));
+    }
+
+    #[test]
+    fn test_repair_with_insert_and_delete() {
+        let decoder = Decoder::new(Grammar::default());
+        let repair = decoder.repair("(a)", 2).unwrap();
+        assert_eq!(vec![Edit::Delete(1)], repair.edits);
+        assert_eq!("()", repair.line);
+
+        let repair = decoder.repair("())", 2).unwrap();
+        assert_eq!(1, repair.edits.len());
+        assert!(decode_chunk(&repair.line)
+            .iter()
+            .all(|result| *result == DecoderResult::Ok));
+
+        let repair = decoder.repair(")}]", 2).unwrap();
+        assert_eq!(2, repair.edits.len());
+        assert_eq!(None, decoder.repair(")}]", 1));
+    }
+
+    #[test]
+    fn test_complete_incomplete_lines() {
+        let decoder = Decoder::new(Grammar::default());
+        let repair = decoder.repair("[({(<(())[]>[[{[]{<()<>>", 0).unwrap();
+        assert!(repair.edits.is_empty());
+        assert_eq!("[({(<(())[]>[[{[]{<()<>>", repair.line);
+        assert_eq!("}}]])})]", repair.completion);
+        assert_eq!(Some(288957), repair.completion_score(&decoder.grammar));
+    }
+
+    #[test]
+    fn test_score_custom_grammar_completion() {
+        let decoder = Decoder::new(Grammar::new(&[('(', ')'), ('"', '"'), ('|', '|')]));
+        let repair = decoder.repair(r#"(|"#, 0).unwrap();
+        assert_eq!("|)", repair.completion);
+        assert_eq!(Some(3 * 4 + 1), repair.completion_score(&decoder.grammar));
+        assert_eq!(None, incomplete_score("|)", &Grammar::default()));
+    }
 }
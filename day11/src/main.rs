@@ -1,15 +1,38 @@
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use itertools::Itertools;
 
-#[derive(Debug, Clone, PartialEq)]
-struct Grid {
-    pub width: u32,
-    pub height: u32,
-    pub fields: Vec<u8>,
+/// The neighborhood a flash spreads to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Neighborhood {
+    /// The four orthogonal neighbors
+    VonNeumann,
+    /// All eight surrounding neighbors
+    Moore,
+    /// Six neighbors of a hex grid, where odd rows are shifted right by half a field
+    Hex,
 }
 
-const NEIGHBORS: [(i32, i32); 8] = [
+impl Neighborhood {
+    /// Returns the offsets of all neighbors for a field in the given row
+    pub fn offsets(&self, y: u32) -> &'static [(i32, i32)] {
+        match self {
+            Neighborhood::VonNeumann => &VON_NEUMANN,
+            Neighborhood::Moore => &MOORE,
+            Neighborhood::Hex if y.is_multiple_of(2) => &HEX_EVEN,
+            Neighborhood::Hex => &HEX_ODD,
+        }
+    }
+}
+
+const VON_NEUMANN: [(i32, i32); 4] = [(0, -1), (-1, 0), (1, 0), (0, 1)];
+
+const MOORE: [(i32, i32); 8] = [
     (-1, -1),
     (0, -1),
     (1, -1),
@@ -20,111 +43,425 @@ const NEIGHBORS: [(i32, i32); 8] = [
     (1, 1),
 ];
 
+const HEX_EVEN: [(i32, i32); 6] = [(-1, -1), (0, -1), (-1, 0), (1, 0), (-1, 1), (0, 1)];
+
+const HEX_ODD: [(i32, i32); 6] = [(0, -1), (1, -1), (-1, 0), (1, 0), (0, 1), (1, 1)];
+
+/// The rules of the automaton, the default rules are the ones of the octopuses.
+#[derive(Debug, Clone, PartialEq)]
+struct Rules {
+    /// A field flashes once its energy exceeds the threshold
+    pub threshold: u8,
+    /// The energy level a field is reset to after a flash
+    pub reset: u8,
+    pub neighborhood: Neighborhood,
+    /// Flashes spread across the edges to the opposite side of the grid
+    pub wrap: bool,
+    /// Maximum energy level per field, a field capped at or below the threshold never flashes
+    pub caps: Option<Vec<u8>>,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            threshold: 9,
+            reset: 0,
+            neighborhood: Neighborhood::Moore,
+            wrap: false,
+            caps: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Grid {
+    pub width: u32,
+    pub height: u32,
+    pub fields: Vec<u8>,
+    pub rules: Rules,
+}
+
 impl Grid {
     pub fn new(width: u32, height: u32, fields: Vec<u8>) -> Self {
         Self {
             width,
             height,
             fields,
+            rules: Rules::default(),
+        }
+    }
+
+    /// Sets the rules the grid advances by
+    pub fn with_rules(mut self, rules: Rules) -> Self {
+        if let Some(caps) = &rules.caps {
+            assert_eq!(self.fields.len(), caps.len());
         }
+        self.rules = rules;
+        self
     }
 
-    /// Returns true when all fields are zero
+    /// Returns true when all fields are at the reset level, e.g. after all flashed
     pub fn is_synched(&self) -> bool {
         self.flashes() == self.width as usize * self.height as usize
     }
 
-    /// Returns the number of flashes
+    /// Returns the number of fields at the reset level, e.g. the ones that flashed
     pub fn flashes(&self) -> usize {
-        self.fields.iter().filter(|&&val| val == 0).count()
+        self.fields
+            .iter()
+            .filter(|&&val| val == self.rules.reset)
+            .count()
     }
 
-    /// Get the energy level of a field if available
-    pub fn get(&self, x: u32, y: u32) -> u8 {
-        assert!(x < self.width);
-        assert!(y < self.height);
-        self.fields[(y * self.width + x) as usize]
+    /// Increases the energy level of the field by one, up to its cap
+    fn charge(&mut self, index: usize) {
+        let cap = match &self.rules.caps {
+            Some(caps) => caps[index],
+            None => u8::MAX,
+        };
+        let value = &mut self.fields[index];
+        *value = value.saturating_add(1).min(cap);
     }
 
-    pub fn inc(&mut self, x: u32, y: u32) {
-        assert!(x < self.width);
-        assert!(y < self.height);
-        let value = &mut self.fields[(y * self.width + x) as usize];
-        *value += 1;
+    /// Returns the indices of all neighbors of the field
+    fn neighbors(&self, index: usize) -> Vec<usize> {
+        let (width, height) = (self.width as i32, self.height as i32);
+        let x = index as i32 % width;
+        let y = index as i32 / width;
+
+        self.rules
+            .neighborhood
+            .offsets(y as u32)
+            .iter()
+            .filter_map(|&(nx, ny)| {
+                let (nx, ny) = (x + nx, y + ny);
+                if self.rules.wrap {
+                    Some((nx.rem_euclid(width), ny.rem_euclid(height)))
+                } else if 0 <= nx && nx < width && 0 <= ny && ny < height {
+                    Some((nx, ny))
+                } else {
+                    None
+                }
+            })
+            .map(|(nx, ny)| (ny * width + nx) as usize)
+            .filter(|&neighbor| neighbor != index)
+            .unique()
+            .collect_vec()
+    }
+
+    /// Advance the grid by a single step, returns the number of flashes
+    pub fn single_step(&mut self) -> usize {
+        self.cascade().iter().map(Vec::len).sum()
     }
 
-    /// Reset the field after a flash back to energy level 0
-    pub fn reset(&mut self, x: u32, y: u32) {
-        assert!(x < self.width);
-        assert!(y < self.height);
-        *(&mut self.fields[(y * self.width + x) as usize]) = 0;
+    /// Advance the grid by a single step, returns the flashed fields wave by wave.
+    ///
+    /// The first wave contains all fields charged above the threshold, every following wave
+    /// the fields pushed above the threshold by the flashes of the previous wave.
+    pub fn cascade(&mut self) -> Vec<Vec<usize>> {
+        self.observed_cascade(|_| ())
     }
 
-    /// Advance the grid by a single step, returns the new grid and the number of flashes
-    pub fn single_step(&mut self) {
+    /// Runs a cascade like [`Grid::cascade`], `observe` sees the energy levels after the
+    /// initial charge and after every wave, before flashed fields are reset.
+    fn observed_cascade(&mut self, mut observe: impl FnMut(&[u8])) -> Vec<Vec<usize>> {
         // Increase all fields by one
-        for y in 0..self.height {
-            for x in 0..self.width {
-                self.inc(x, y);
-            }
+        for index in 0..self.fields.len() {
+            self.charge(index);
         }
 
-        loop {
-            let mut flash_happened = false;
-            for y in 0..self.height {
-                for x in 0..self.width {
-                    let value = self.get(x, y);
-
-                    // TODO refactor the following block
-                    if value > 9 {
-                        self.reset(x, y);
-                        flash_happened = true;
-
-                        // check all neighbors
-                        for &(nx, ny) in NEIGHBORS.iter() {
-                            let nx = nx + x as i32;
-                            let ny = ny + y as i32;
-                            if 0 <= nx
-                                && nx < self.width as i32
-                                && 0 <= ny
-                                && ny < self.height as i32
-                            {
-                                if self.get(nx as u32, ny as u32) > 0 {
-                                    self.inc(nx as u32, ny as u32);
-                                }
-                            }
+        let threshold = self.rules.threshold;
+        let mut flashed = vec![false; self.fields.len()];
+        let mut waves = Vec::new();
+        let mut wave = (0..self.fields.len())
+            .filter(|&index| self.fields[index] > threshold)
+            .collect_vec();
+
+        // every field flashes at most once, its flash charges all neighbors that did not flash yet
+        observe(&self.fields);
+        while !wave.is_empty() {
+            for &index in wave.iter() {
+                flashed[index] = true;
+            }
+
+            let mut next = Vec::new();
+            for &index in wave.iter() {
+                for neighbor in self.neighbors(index) {
+                    if !flashed[neighbor] {
+                        self.charge(neighbor);
+                        if self.fields[neighbor] > threshold && !next.contains(&neighbor) {
+                            next.push(neighbor);
                         }
                     }
                 }
             }
 
-            // return if no flash happened
-            if !flash_happened {
+            waves.push(wave);
+            wave = next.into_iter().sorted().collect_vec();
+            observe(&self.fields);
+        }
+
+        for &index in waves.iter().flatten() {
+            self.fields[index] = self.rules.reset;
+        }
+        waves
+    }
+
+    /// Advances the grid by a number of steps and records every step
+    pub fn record(&self, count: u64) -> Recording {
+        let mut grid = self.clone();
+        let frames = (1..=count)
+            .map(|step| {
+                let mut energies = Vec::new();
+                let waves = grid.observed_cascade(|fields| energies.push(fields.to_vec()));
+                Frame {
+                    step,
+                    fields: grid.fields.clone(),
+                    waves,
+                    energies,
+                }
+            })
+            .collect_vec();
+
+        Recording {
+            width: self.width,
+            height: self.height,
+            initial: self.fields.clone(),
+            frames,
+        }
+    }
+
+    /// Advances the grid by a number of steps, returns the resulting grid & number of observed flashes
+    ///
+    /// Once the grid repeats a previous state the remaining steps are skipped by using the cycle,
+    /// therefore even a huge number of steps can be answered.
+    pub fn steps(&self, count: u64) -> (Grid, u64) {
+        let mut grid = self.clone();
+        let mut flashes = 0;
+        let mut brent = Brent::new(self);
+        for step in 1..=count {
+            flashes += grid.single_step() as u64;
+            // the grid is within the cycle now, all full cycles left can be skipped
+            if let Some((period, per_cycle)) = brent.observe(&grid, flashes) {
+                let remaining = count - step;
+                flashes += remaining / period * per_cycle;
+                flashes += grid.advance(remaining % period);
                 break;
             }
         }
+        (grid, flashes)
     }
 
-    /// Advances the grid by a number of steps, returns the resulting grid & number of observed flashes
-    pub fn steps(&self, count: u32) -> (Grid, u32) {
-        (0..count).fold((self.clone(), 0), |(mut grid, flashes), _| {
-            grid.single_step();
-            let next_flashes = grid.flashes() as u32;
-            (grid, flashes + next_flashes)
-        })
+    /// Advances the grid by a number of steps, returns the number of flashes
+    fn advance(&mut self, count: u64) -> u64 {
+        (0..count).map(|_| self.single_step() as u64).sum()
+    }
+
+    /// Finds the cycle the grid eventually runs into, looks at most `limit` steps ahead.
+    ///
+    /// Uses Brent's algorithm, so only two grid states are kept no matter how long the run is.
+    pub fn find_cycle(&self, limit: u64) -> Option<Cycle> {
+        // the hare needs at most three times the steps to the end of the first cycle to find it
+        let budget = limit.saturating_mul(3);
+        let mut hare = self.clone();
+        let mut brent = Brent::new(self);
+        let (period, _) = (0..budget).find_map(|_| {
+            hare.single_step();
+            brent.observe(&hare, 0)
+        })?;
+
+        // a hare `period` steps ahead meets the tortoise at the first repeating state
+        let mut tortoise = self.clone();
+        let mut hare = self.clone();
+        hare.advance(period);
+        let mut offset = 0;
+        while tortoise.fields != hare.fields {
+            tortoise.single_step();
+            hare.single_step();
+            offset += 1;
+        }
+
+        (offset + period <= limit).then_some(Cycle { offset, period })
     }
 
     /// Determines when all octopuses are in sync, returns the step when this first occurs.
-    pub fn find_synched_step(&self) -> u32 {
+    ///
+    /// Returns `None` when the grid runs into a cycle before all of them flash at once.
+    pub fn find_synched_step(&self) -> Option<u64> {
         let mut grid = self.clone();
+        let mut brent = Brent::new(self);
         let mut step = 0;
 
         while !grid.is_synched() {
-            grid.single_step();
+            let flashes = grid.single_step();
             step += 1;
+            if flashes == grid.fields.len() {
+                break;
+            }
+            if brent.observe(&grid, 0).is_some() {
+                return None;
+            }
+        }
+
+        Some(step)
+    }
+}
+
+/// Brent's cycle detection, fed with the grid after every step. Only a single earlier state
+/// is kept, it moves ahead whenever the number of steps since it reaches the next power of two.
+struct Brent {
+    snapshot: Vec<u8>,
+    /// The total number of flashes when the snapshot was taken
+    flashes: u64,
+    power: u64,
+    period: u64,
+}
+
+impl Brent {
+    fn new(grid: &Grid) -> Self {
+        Self {
+            snapshot: grid.fields.clone(),
+            flashes: 0,
+            power: 1,
+            period: 0,
+        }
+    }
+
+    /// Observes the grid after the next step with the total number of flashes so far.
+    ///
+    /// Once the grid repeats the snapshot, returns the period and the flashes within a period.
+    fn observe(&mut self, grid: &Grid, flashes: u64) -> Option<(u64, u64)> {
+        self.period += 1;
+        if grid.fields == self.snapshot {
+            return Some((self.period, flashes - self.flashes));
+        }
+        if self.period == self.power {
+            self.snapshot = grid.fields.clone();
+            self.flashes = flashes;
+            self.power *= 2;
+            self.period = 0;
+        }
+        None
+    }
+}
+
+/// A cycle in the sequence of grid states
+#[derive(Debug, Clone, PartialEq)]
+struct Cycle {
+    /// The step of the first state that repeats
+    pub offset: u64,
+    /// The number of steps until the state repeats
+    pub period: u64,
+}
+
+/// A single recorded step of the grid
+#[derive(Debug, Clone, PartialEq)]
+struct Frame {
+    pub step: u64,
+    /// The energy levels after the step
+    pub fields: Vec<u8>,
+    /// The flashed fields in cascade order, grouped by wave
+    pub waves: Vec<Vec<usize>>,
+    /// The energy levels after charging and after each wave, before flashed fields are reset
+    pub energies: Vec<Vec<u8>>,
+}
+
+impl Frame {
+    /// Returns the wave the field flashed in
+    pub fn wave_of(&self, index: usize) -> Option<usize> {
+        self.waves.iter().position(|wave| wave.contains(&index))
+    }
+}
+
+/// A recorded run of the grid, that can be played back or exported
+#[derive(Debug, Clone, PartialEq)]
+struct Recording {
+    pub width: u32,
+    pub height: u32,
+    /// The energy levels before the first step
+    pub initial: Vec<u8>,
+    pub frames: Vec<Frame>,
+}
+
+impl Recording {
+    /// Renders the frame after the given number of waves of its cascade.
+    ///
+    /// Fields that flashed so far are highlighted, all others are shaded by their energy level
+    /// at that wave, fields charged past 9 are drawn as `+`.
+    pub fn render_ansi(&self, frame: &Frame, waves: usize) -> String {
+        let energies = &frame.energies[waves.min(frame.energies.len() - 1)];
+        let mut output = format!("step {:>4}, wave {:>2}\n", frame.step, waves);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let index = (y * self.width + x) as usize;
+                match frame.wave_of(index) {
+                    Some(wave) if wave < waves => {
+                        output += &format!(
+                            "\x1b[1;30;10{}m{}\x1b[0m",
+                            3 + wave % 5,
+                            frame.fields[index]
+                        )
+                    }
+                    _ => {
+                        let energy = energies[index];
+                        let shade = 238 + 2 * energy.min(8);
+                        let glyph = char::from_digit(energy as u32, 10).unwrap_or('+');
+                        output += &format!("\x1b[38;5;{}m{}\x1b[0m", shade, glyph)
+                    }
+                }
+            }
+            output += "\n";
+        }
+        output
+    }
+
+    /// Plays the recording in the terminal, every wave of a cascade is shown on its own.
+    pub fn play<W: Write>(&self, out: &mut W, delay: Duration) -> std::io::Result<()> {
+        for frame in self.frames.iter() {
+            for waves in 0..=frame.waves.len() {
+                // clear the screen and move the cursor to the top left
+                write!(out, "\x1b[2J\x1b[H{}", self.render_ansi(frame, waves))?;
+                out.flush()?;
+                std::thread::sleep(delay);
+            }
         }
+        Ok(())
+    }
+
+    /// Writes every frame as a binary PPM image into the directory, returns the written files.
+    ///
+    /// Each field is drawn as a square of `scale` pixels, flashed fields fade from white to red
+    /// by the wave they flashed in, all others are gray by their energy level.
+    pub fn export_ppm(&self, dir: &Path, scale: u32) -> std::io::Result<Vec<PathBuf>> {
+        std::fs::create_dir_all(dir)?;
+
+        let (width, height) = (self.width * scale, self.height * scale);
+        self.frames
+            .iter()
+            .map(|frame| {
+                let mut image = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+                for y in 0..height {
+                    for x in 0..width {
+                        let index = ((y / scale) * self.width + x / scale) as usize;
+                        let color = match frame.wave_of(index) {
+                            Some(wave) => {
+                                let fade = (wave as u32 * 40).min(200) as u8;
+                                [255, 255 - fade, 255 - fade]
+                            }
+                            None => {
+                                let gray = frame.fields[index].min(9) * 20;
+                                [gray, gray, gray]
+                            }
+                        };
+                        image.extend_from_slice(&color);
+                    }
+                }
 
-        step
+                let path = dir.join(format!("frame_{:04}.ppm", frame.step));
+                std::fs::write(&path, image)?;
+                Ok(path)
+            })
+            .collect()
     }
 }
 
@@ -171,16 +508,56 @@ fn main() {
     "#;
     let grid = parse_input(input);
 
+    // `--play` shows the first 100 steps in the terminal, `--export <dir>` writes them as images
+    let args = std::env::args().collect_vec();
+    if args.iter().any(|arg| arg == "--play") {
+        let recording = grid.record(100);
+        recording
+            .play(&mut std::io::stdout(), Duration::from_millis(50))
+            .expect("Failed to play recording");
+    }
+    if let Some(dir) = args.iter().skip_while(|&arg| arg != "--export").nth(1) {
+        let recording = grid.record(100);
+        let files = recording
+            .export_ppm(Path::new(dir), 16)
+            .expect("Failed to export recording");
+        println!("Exported {} frames to {}", files.len(), dir);
+    }
+
     let (_, flashes) = grid.steps(100);
     dbg!(flashes);
 
     let step = grid.find_synched_step();
     dbg!(step);
+
+    let cycle = grid.find_cycle(10_000);
+    dbg!(cycle);
+
+    let (_, flashes) = grid.steps(1_000_000_000_000);
+    dbg!(flashes);
+
+    let rules = Rules {
+        neighborhood: Neighborhood::Hex,
+        wrap: true,
+        ..Rules::default()
+    };
+    let (_, flashes) = grid.clone().with_rules(rules).steps(100);
+    dbg!(flashes);
+
+    let rules = Rules {
+        threshold: 5,
+        reset: 1,
+        neighborhood: Neighborhood::VonNeumann,
+        caps: Some(vec![7; grid.fields.len()]),
+        ..Rules::default()
+    };
+    let (_, flashes) = grid.with_rules(rules).steps(100);
+    dbg!(flashes);
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::parse_input;
+    use crate::{parse_input, Cycle, Neighborhood, Rules};
 
     const INPUT: &str = r#"
         5483143223
@@ -302,6 +679,162 @@ mod tests {
     #[test]
     fn test_find_synched_step() {
         let grid = parse_input(INPUT);
-        assert_eq!(195, grid.find_synched_step());
+        assert_eq!(Some(195), grid.find_synched_step());
+    }
+
+    #[test]
+    fn test_von_neumann_neighborhood() {
+        let rules = Rules {
+            neighborhood: Neighborhood::VonNeumann,
+            ..Rules::default()
+        };
+        let mut grid = parse_input("000\n090\n000").with_rules(rules);
+        assert_eq!(1, grid.single_step());
+        assert_eq!(parse_input("121\n202\n121").fields, grid.fields);
+    }
+
+    #[test]
+    fn test_wrap_around_edges() {
+        let rules = Rules {
+            neighborhood: Neighborhood::VonNeumann,
+            wrap: true,
+            ..Rules::default()
+        };
+        let mut grid = parse_input("9000\n0000\n0000").with_rules(rules);
+        assert_eq!(1, grid.single_step());
+        assert_eq!(parse_input("0212\n2111\n2111").fields, grid.fields);
+    }
+
+    #[test]
+    fn test_hex_neighborhood() {
+        let rules = Rules {
+            neighborhood: Neighborhood::Hex,
+            ..Rules::default()
+        };
+        let mut grid = parse_input("000\n090\n000").with_rules(rules);
+        assert_eq!(1, grid.single_step());
+        assert_eq!(parse_input("122\n202\n122").fields, grid.fields);
+    }
+
+    #[test]
+    fn test_energy_caps_and_threshold() {
+        let rules = Rules {
+            threshold: 3,
+            reset: 1,
+            caps: Some(vec![9, 3, 9, 9]),
+            ..Rules::default()
+        };
+        let mut grid = parse_input("33\n00").with_rules(rules);
+        assert_eq!(1, grid.single_step());
+        assert_eq!(parse_input("13\n22").fields, grid.fields);
+    }
+
+    #[test]
+    fn test_find_synched_step_with_rules() {
+        let rules = Rules {
+            neighborhood: Neighborhood::VonNeumann,
+            wrap: true,
+            ..Rules::default()
+        };
+        let grid = parse_input("5555\n5555").with_rules(rules);
+        assert_eq!(Some(5), grid.find_synched_step());
+    }
+
+    #[test]
+    fn test_find_cycle() {
+        let grid = parse_input(INPUT);
+        let cycle = grid.find_cycle(1_000).unwrap();
+        assert_eq!(10, cycle.period);
+        assert_eq!(195, cycle.offset);
+        assert_eq!(None, grid.find_cycle(100));
+    }
+
+    #[test]
+    fn test_steps_beyond_cycle() {
+        let grid = parse_input(INPUT);
+        let mut expected = grid.clone();
+        let flashes = (0..1_000)
+            .map(|_| expected.single_step() as u64)
+            .sum::<u64>();
+        assert_eq!((expected, flashes), grid.steps(1_000));
+
+        let (synched, flashes) = grid.steps(195);
+        let (result, total) = grid.steps(1_000_000_000_005);
+        assert_eq!(synched, result);
+        assert_eq!(flashes + 99_999_999_981 * 100, total);
+    }
+
+    #[test]
+    fn test_never_synchronises() {
+        let rules = Rules {
+            caps: Some(vec![5; 100]),
+            ..Rules::default()
+        };
+        let grid = parse_input(INPUT).with_rules(rules);
+        assert_eq!(None, grid.find_synched_step());
+        assert_eq!(
+            Some(Cycle {
+                offset: 4,
+                period: 1
+            }),
+            grid.find_cycle(100)
+        );
+    }
+
+    #[test]
+    fn test_record_cascade() {
+        let grid = parse_input("11111\n19991\n19191\n19991\n11111");
+        let recording = grid.record(2);
+        assert_eq!(2, recording.frames.len());
+
+        let frame = &recording.frames[0];
+        assert_eq!(1, frame.step);
+        assert_eq!(
+            vec![vec![6, 7, 8, 11, 13, 16, 17, 18], vec![12]],
+            frame.waves
+        );
+        assert_eq!(Some(1), frame.wave_of(12));
+        assert_eq!(None, frame.wave_of(0));
+        assert_eq!(
+            parse_input("34543\n40004\n50005\n40004\n34543").fields,
+            frame.fields
+        );
+        assert!(recording.frames[1].waves.is_empty());
+
+        assert_eq!(3, frame.energies.len());
+        assert_eq!(
+            vec![2, 2, 2, 2, 2, 2, 10, 10, 10, 2, 2, 10, 2, 10, 2, 2, 10, 10, 10, 2, 2, 2, 2, 2, 2],
+            frame.energies[0]
+        );
+        assert_eq!(10, frame.energies[1][12]);
+    }
+
+    #[test]
+    fn test_render_ansi_frame() {
+        let recording = parse_input("90\n00").record(1);
+        let frame = &recording.frames[0];
+        assert_eq!(
+            "step    1, wave  1\n\x1b[1;30;103m0\x1b[0m\x1b[38;5;242m2\x1b[0m\n\x1b[38;5;242m2\x1b[0m\x1b[38;5;242m2\x1b[0m\n",
+            recording.render_ansi(frame, 1)
+        );
+        assert_eq!(
+            "step    1, wave  0\n\x1b[38;5;254m+\x1b[0m\x1b[38;5;240m1\x1b[0m\n\x1b[38;5;240m1\x1b[0m\x1b[38;5;240m1\x1b[0m\n",
+            recording.render_ansi(frame, 0)
+        );
+    }
+
+    #[test]
+    fn test_export_image_sequence() -> std::io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("day11-frames-{}", std::process::id()));
+        let recording = parse_input(INPUT).record(3);
+        let files = recording.export_ppm(&dir, 2)?;
+        assert_eq!(3, files.len());
+
+        let image = std::fs::read(&files[0])?;
+        let header = b"P6\n20 20\n255\n";
+        assert_eq!(&header[..], &image[..header.len()]);
+        assert_eq!(header.len() + 20 * 20 * 3, image.len());
+
+        std::fs::remove_dir_all(dir)
     }
 }
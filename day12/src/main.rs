@@ -1,9 +1,14 @@
-use std::{collections::HashMap, fmt::{Debug, Display}};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    fmt::{Debug, Display},
+    hash::Hash,
+};
 
+use anyhow::bail;
 use itertools::Itertools;
 
 /// A single node in the graph, can be shared by multiple edges
-#[derive(Clone, Hash, PartialEq, Eq)]
+#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 struct Node(String);
 
 impl Debug for Node {
@@ -40,61 +45,575 @@ impl Node {
     }
 }
 
-/// Returns true if all small cave nodes in the given list are unique
-fn is_unique(list: &[Node]) -> bool {
-    let list = list.iter().filter(|node| node.small()).collect_vec();
-    list.len() == list.iter().unique().count()
+/// A cave as seen by a [`VisitPolicy`], together with the id the graph interned it under
+#[derive(Debug, Clone, Copy)]
+struct Cave<'a> {
+    pub id: usize,
+    pub node: &'a Node,
+    /// The bit of a small cave in a bit mask of small caves, 0 for big caves
+    pub bit: u64,
+}
+
+/// Decides which caves a path may enter, its state tracks everything the decision depends on
+trait VisitPolicy {
+    type State: Clone + Eq + Hash;
+
+    /// The state of an empty path
+    fn initial(&self) -> Self::State;
+
+    /// Returns the state after entering the cave, `None` if the cave may not be entered
+    fn enter(&self, state: &Self::State, cave: Cave) -> Option<Self::State>;
+
+    /// Returns true if a path that reached the end with the given state is valid
+    fn accept(&self, _state: &Self::State) -> bool {
+        true
+    }
+
+    /// Fails if the state can not track the caves of the graph
+    fn check(&self, _graph: &Graph) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Never enters any of the given caves
+    fn forbid(self, caves: impl IntoIterator<Item = Node>) -> Forbidden<Self>
+    where
+        Self: Sized,
+    {
+        Forbidden {
+            policy: self,
+            caves: caves.into_iter().collect(),
+        }
+    }
+
+    /// Only accepts paths that pass all of the given caves
+    fn through(self, caves: impl IntoIterator<Item = Node>) -> Waypoints<Self>
+    where
+        Self: Sized,
+    {
+        Waypoints {
+            policy: self,
+            caves: caves.into_iter().collect(),
+        }
+    }
+}
+
+/// Small caves are visited at most once, except for `twice` distinct ones that may be visited
+/// twice.
+/// Big caves can be visited any number of times.
+#[derive(Debug, Clone)]
+struct SmallCaves {
+    pub twice: usize,
+}
+
+impl SmallCaves {
+    pub fn new(twice: usize) -> Self {
+        Self { twice }
+    }
+}
+
+impl VisitPolicy for SmallCaves {
+    /// The visited small caves and the small caves visited twice, as bit masks of small caves
+    type State = (u64, u64);
+
+    fn initial(&self) -> Self::State {
+        (0, 0)
+    }
+
+    fn enter(&self, &(visited, doubled): &Self::State, cave: Cave) -> Option<Self::State> {
+        let bit = cave.bit;
+        if cave.node.big() {
+            Some((visited, doubled))
+        } else if visited & bit == 0 {
+            Some((visited | bit, doubled))
+        } else if doubled & bit == 0
+            && (doubled.count_ones() as usize) < self.twice
+            && !cave.node.is_start()
+            && !cave.node.is_end()
+        {
+            Some((visited, doubled | bit))
+        } else {
+            None
+        }
+    }
+
+    fn check(&self, graph: &Graph) -> anyhow::Result<()> {
+        let small = graph.caves.iter().filter(|node| node.small()).count();
+        if small > u64::BITS as usize {
+            bail!("Too many small caves: {}", small);
+        }
+        Ok(())
+    }
+}
+
+/// Every cave has a maximum number of visits, small caves default to `small`, big caves
+/// are unlimited unless they are given a budget.
+#[derive(Debug, Clone)]
+struct VisitBudget {
+    pub small: usize,
+    pub budgets: HashMap<Node, usize>,
+}
+
+impl VisitBudget {
+    pub fn new(small: usize) -> Self {
+        Self {
+            small,
+            budgets: HashMap::new(),
+        }
+    }
+
+    /// Sets the number of visits for a single cave
+    pub fn with_budget(mut self, cave: Node, visits: usize) -> Self {
+        self.budgets.insert(cave, visits);
+        self
+    }
+}
+
+impl VisitPolicy for VisitBudget {
+    /// The number of visits of every budgeted cave by its id
+    type State = BTreeMap<usize, usize>;
+
+    fn initial(&self) -> Self::State {
+        BTreeMap::new()
+    }
+
+    fn enter(&self, state: &Self::State, cave: Cave) -> Option<Self::State> {
+        let budget = match self.budgets.get(cave.node) {
+            Some(&budget) => budget,
+            None if cave.node.small() => self.small,
+            None => return Some(state.clone()),
+        };
+        let visits = state.get(&cave.id).copied().unwrap_or(0);
+        if visits >= budget {
+            return None;
+        }
+        let mut state = state.clone();
+        state.insert(cave.id, visits + 1);
+        Some(state)
+    }
+}
+
+/// Wraps a policy, never entering any of the forbidden caves
+#[derive(Debug, Clone)]
+struct Forbidden<P> {
+    pub policy: P,
+    pub caves: HashSet<Node>,
+}
+
+impl<P: VisitPolicy> VisitPolicy for Forbidden<P> {
+    type State = P::State;
+
+    fn initial(&self) -> Self::State {
+        self.policy.initial()
+    }
+
+    fn enter(&self, state: &Self::State, cave: Cave) -> Option<Self::State> {
+        if self.caves.contains(cave.node) {
+            return None;
+        }
+        self.policy.enter(state, cave)
+    }
+
+    fn accept(&self, state: &Self::State) -> bool {
+        self.policy.accept(state)
+    }
+
+    fn check(&self, graph: &Graph) -> anyhow::Result<()> {
+        self.policy.check(graph)
+    }
+}
+
+/// Wraps a policy, only accepting paths that visit all waypoints
+#[derive(Debug, Clone)]
+struct Waypoints<P> {
+    pub policy: P,
+    pub caves: BTreeSet<Node>,
+}
+
+impl<P: VisitPolicy> VisitPolicy for Waypoints<P> {
+    /// The wrapped state and the waypoints visited so far as a bit mask of their positions
+    type State = (P::State, u64);
+
+    fn initial(&self) -> Self::State {
+        (self.policy.initial(), 0)
+    }
+
+    fn enter(&self, (state, visited): &Self::State, cave: Cave) -> Option<Self::State> {
+        let state = self.policy.enter(state, cave)?;
+        if let Some(position) = self.caves.iter().position(|node| node == cave.node) {
+            return Some((state, visited | 1 << position));
+        }
+        Some((state, *visited))
+    }
+
+    fn accept(&self, (state, visited): &Self::State) -> bool {
+        visited.count_ones() as usize == self.caves.len() && self.policy.accept(state)
+    }
+
+    fn check(&self, graph: &Graph) -> anyhow::Result<()> {
+        if self.caves.len() > u64::BITS as usize {
+            bail!("Too many waypoints: {}", self.caves.len());
+        }
+        self.policy.check(graph)
+    }
 }
 
 #[derive(Debug, Clone)]
 struct Graph {
     pub map: HashMap<Node, Vec<Node>>,
+    /// All caves in order of appearance, the position is the id of the cave
+    pub caves: Vec<Node>,
+    ids: HashMap<Node, usize>,
+    /// The bit of every cave in a bit mask of small caves, see [`Cave::bit`]
+    bits: Vec<u64>,
 }
 
 impl Graph {
     pub fn new() -> Self {
         Self {
             map: HashMap::new(),
+            caves: Vec::new(),
+            ids: HashMap::new(),
+            bits: Vec::new(),
         }
     }
 
     /// Adds a new edge to the graph
     pub fn add_edges(&mut self, left: Node, right: Node) {
+        for node in [&left, &right] {
+            if !self.ids.contains_key(node) {
+                // only small caves get a bit, policies that track more of them fail their check
+                let small = self.bits.iter().filter(|&&bit| bit != 0).count();
+                let bit = match node.small() {
+                    true => 1u64.checked_shl(small as u32).unwrap_or(0),
+                    false => 0,
+                };
+                self.ids.insert(node.clone(), self.caves.len());
+                self.caves.push(node.clone());
+                self.bits.push(bit);
+            }
+        }
         for (x, y) in [(&left, &right), (&right, &left)] {
             // ignore paths that start with "end" or end with "start"
             if !x.is_end() && !y.is_start() {
-                self.map
-                    .entry(x.clone())
-                    .or_insert(Vec::new())
-                    .push(y.clone());
+                self.map.entry(x.clone()).or_default().push(y.clone());
             }
         }
     }
 
-    pub fn count_all_paths(&self, visit_twice: bool) -> usize {
-        Self::find_paths(vec![Node::new("start".to_string())], &self.map, visit_twice).len()
+    /// Counts all paths from start to end allowed by the policy, without materializing any path.
+    ///
+    /// Counts recursively, memoized on the current cave and the policy state. Returns `None`
+    /// if the policy allows infinitely many paths, fails if the policy can not track the caves.
+    pub fn count_all_paths<P: VisitPolicy>(&self, policy: &P) -> anyhow::Result<Option<usize>> {
+        policy.check(self)?;
+        let edges = self
+            .caves
+            .iter()
+            .map(|node| {
+                self.map
+                    .get(node)
+                    .map(|next| next.iter().map(|node| self.ids[node]).collect_vec())
+                    .unwrap_or_default()
+            })
+            .collect_vec();
+
+        let Some(start) = self.cave(&Node::new("start".to_string())) else {
+            return Ok(Some(0));
+        };
+        let Some(state) = policy.enter(&policy.initial(), start) else {
+            return Ok(Some(0));
+        };
+        let search = PathCounter {
+            edges,
+            end: self.caves.iter().position(Node::is_end),
+            graph: self,
+            policy,
+        };
+        let mut memo = Memo::new();
+        let count = search.count(start.id, state, &mut memo);
+        Ok((!memo.is_infinite()).then_some(count))
     }
 
-    /// Traverse all paths via DFS, return the list of paths found
-    pub fn find_paths(visited: Vec<Node>, edges: &HashMap<Node, Vec<Node>>, visit_twice: bool) -> Vec<Vec<Node>> {
-        let last_node = visited.last().expect("No last node found");
-        if last_node.is_end() {
-            vec![visited]
-        } else {
-            let mut results = Vec::new();
-
-            let can_visit_twice = visit_twice && is_unique(&visited);
-            for next_node in edges.get(last_node).expect("No edges found for node") {
-                if !visited.contains(next_node) || next_node.big() || can_visit_twice {
-                    // copy current path for next step
-                    let mut next_visited = visited.clone();
-                    next_visited.push(next_node.clone());
-                    results.append(&mut Self::find_paths(next_visited, edges, visit_twice));
+    /// Returns the interned cave of the node, `None` if the node is not part of the graph
+    pub fn cave(&self, node: &Node) -> Option<Cave<'_>> {
+        self.ids.get(node).map(|&id| self.cave_by_id(id))
+    }
+
+    /// Returns the interned cave with the given id
+    fn cave_by_id(&self, id: usize) -> Cave<'_> {
+        Cave {
+            id,
+            node: &self.caves[id],
+            bit: self.bits[id],
+        }
+    }
+
+    /// Returns true if there are infinitely many paths from start to end, e.g. because two big
+    /// caves are connected and can be visited back and forth.
+    pub fn has_infinite_paths(&self) -> bool {
+        // a budget tracks any number of small caves, unlike the bit masks of `SmallCaves`
+        self.count_all_paths(&VisitBudget::new(1))
+            .is_ok_and(|count| count.is_none())
+    }
+
+    /// Returns the edges in both directions, including the ones leading back to start or
+    /// away from end
+    fn undirected(&self) -> BTreeMap<&Node, BTreeSet<&Node>> {
+        let mut edges = BTreeMap::<_, BTreeSet<_>>::new();
+        for (x, next) in self.map.iter() {
+            for y in next {
+                edges.entry(x).or_default().insert(y);
+                edges.entry(y).or_default().insert(x);
+            }
+        }
+        edges
+    }
+
+    /// Returns one of the shortest paths from start to end, using a BFS
+    pub fn shortest_path(&self) -> Option<Vec<Node>> {
+        let start = Node::new("start".to_string());
+        let mut previous = HashMap::<&Node, &Node>::new();
+        let mut queue = VecDeque::from([&start]);
+        while let Some(node) = queue.pop_front() {
+            if node.is_end() {
+                let mut path = vec![node.clone()];
+                let mut node = node;
+                while let Some(&prev) = previous.get(node) {
+                    path.push(prev.clone());
+                    node = prev;
                 }
+                path.reverse();
+                return Some(path);
             }
+            for next in self.map.get(node).into_iter().flatten() {
+                if !next.is_start() && !previous.contains_key(next) {
+                    previous.insert(next, node);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
 
-            results
+    /// Returns all caves that disconnect the cave system when removed, in sorted order.
+    ///
+    /// Uses Tarjan's algorithm on the undirected graph.
+    pub fn articulation_points(&self) -> Vec<Node> {
+        let edges = self.undirected();
+        let mut search = Articulation {
+            edges: &edges,
+            discovered: HashMap::new(),
+            low: HashMap::new(),
+            points: BTreeSet::new(),
+        };
+        for &node in edges.keys() {
+            if !search.discovered.contains_key(node) {
+                search.visit(node, None);
+            }
+        }
+        search.points.into_iter().cloned().collect_vec()
+    }
+
+    /// Returns the graph in the DOT format of Graphviz, small caves are drawn as ellipses,
+    /// big caves as filled boxes.
+    pub fn to_dot(&self) -> String {
+        let edges = self.undirected();
+        let mut dot = String::from("graph caves {\n");
+        for node in edges.keys() {
+            let style = if node.is_start() || node.is_end() {
+                "shape=doublecircle"
+            } else if node.small() {
+                "shape=ellipse"
+            } else {
+                "shape=box, style=filled, fillcolor=lightgrey"
+            };
+            dot += &format!("    \"{}\" [{}];\n", node.0, style);
+        }
+        for (x, next) in edges.iter() {
+            for y in next.iter().filter(|&y| x < y) {
+                dot += &format!("    \"{}\" -- \"{}\";\n", x.0, y.0);
+            }
         }
+        dot += "}\n";
+        dot
+    }
+
+    /// Returns a lazy iterator over all paths from start to end allowed by the policy.
+    ///
+    /// Never ends if there are infinitely many paths, see [`Graph::has_infinite_paths`]. Fails
+    /// if the policy can not track the caves.
+    pub fn paths<'a, P: VisitPolicy>(&'a self, policy: &'a P) -> anyhow::Result<Paths<'a, P>> {
+        policy.check(self)?;
+        let mut paths = Paths {
+            graph: self,
+            policy,
+            path: Vec::new(),
+            states: Vec::new(),
+            next_edges: Vec::new(),
+        };
+        let Some(start) = self.cave(&Node::new("start".to_string())) else {
+            return Ok(paths);
+        };
+        if let Some(state) = policy.enter(&policy.initial(), start) {
+            paths.path.push(start.node.clone());
+            paths.states.push(state);
+            paths.next_edges.push(0);
+        }
+        Ok(paths)
+    }
+}
+
+/// Counts paths over caves given by their ids
+struct PathCounter<'a, P: VisitPolicy> {
+    graph: &'a Graph,
+    edges: Vec<Vec<usize>>,
+    end: Option<usize>,
+    policy: &'a P,
+}
+
+impl<'a, P: VisitPolicy> PathCounter<'a, P> {
+    fn count(&self, cave: usize, state: P::State, memo: &mut Memo<P::State>) -> usize {
+        if Some(cave) == self.end {
+            return usize::from(self.policy.accept(&state));
+        }
+        let key = (cave, state);
+        if let Some(&count) = memo.counts.get(&key) {
+            return count;
+        }
+        // a cave entered again with the same state can be looped forever
+        if !memo.active.insert(key.clone()) {
+            memo.loops.push(key);
+            return 0;
+        }
+
+        let mut count = 0;
+        for &next in self.edges[cave].iter() {
+            let cave = self.graph.cave_by_id(next);
+            if let Some(state) = self.policy.enter(&key.1, cave) {
+                count += self.count(next, state, memo);
+            }
+        }
+
+        memo.active.remove(&key);
+        memo.counts.insert(key, count);
+        count
+    }
+}
+
+/// The memoized path counts of a [`PathCounter`]
+struct Memo<S> {
+    counts: HashMap<(usize, S), usize>,
+    /// The caves and states currently being counted
+    active: HashSet<(usize, S)>,
+    /// The caves and states that were reached again while being counted
+    loops: Vec<(usize, S)>,
+}
+
+impl<S: Clone + Eq + Hash> Memo<S> {
+    fn new() -> Self {
+        Self {
+            counts: HashMap::new(),
+            active: HashSet::new(),
+            loops: Vec::new(),
+        }
+    }
+
+    /// Returns true if any loop leads to the end, which allows infinitely many paths
+    fn is_infinite(&self) -> bool {
+        self.loops
+            .iter()
+            .any(|key| self.counts.get(key).is_some_and(|&count| count > 0))
+    }
+}
+
+/// Finds articulation points via DFS, tracking the discovery time and lowest reachable
+/// discovery time of every cave
+struct Articulation<'a> {
+    edges: &'a BTreeMap<&'a Node, BTreeSet<&'a Node>>,
+    discovered: HashMap<&'a Node, usize>,
+    low: HashMap<&'a Node, usize>,
+    points: BTreeSet<&'a Node>,
+}
+
+impl<'a> Articulation<'a> {
+    fn visit(&mut self, node: &'a Node, parent: Option<&'a Node>) {
+        let time = self.discovered.len();
+        self.discovered.insert(node, time);
+        self.low.insert(node, time);
+
+        let mut children = 0;
+        for &next in self.edges[node].iter() {
+            if Some(next) == parent {
+                continue;
+            }
+            if let Some(&discovered) = self.discovered.get(next) {
+                self.low.insert(node, self.low[node].min(discovered));
+                continue;
+            }
+            children += 1;
+            self.visit(next, Some(node));
+            self.low.insert(node, self.low[node].min(self.low[next]));
+            if parent.is_some() && self.low[next] >= time {
+                self.points.insert(node);
+            }
+        }
+        if parent.is_none() && children > 1 {
+            self.points.insert(node);
+        }
+    }
+}
+
+/// Iterates all paths from start to end via DFS, one path at a time
+struct Paths<'a, P: VisitPolicy> {
+    graph: &'a Graph,
+    policy: &'a P,
+    /// The current path
+    path: Vec<Node>,
+    /// For every node in the path the policy state after entering it
+    states: Vec<P::State>,
+    /// For every node in the path the index of the next edge to follow
+    next_edges: Vec<usize>,
+}
+
+impl<'a, P: VisitPolicy> Iterator for Paths<'a, P> {
+    type Item = Vec<Node>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(next_edge) = self.next_edges.last_mut() {
+            let last_node = self.path.last().expect("No last node found");
+            let next_node = self
+                .graph
+                .map
+                .get(last_node)
+                .and_then(|edges| edges.get(*next_edge));
+
+            let Some(next_node) = next_node else {
+                self.path.pop();
+                self.states.pop();
+                self.next_edges.pop();
+                continue;
+            };
+            *next_edge += 1;
+
+            let state = self.states.last().expect("No state found");
+            let cave = self.graph.cave(next_node).expect("Cave not interned");
+            let Some(state) = self.policy.enter(state, cave) else {
+                continue;
+            };
+            if next_node.is_end() {
+                if self.policy.accept(&state) {
+                    let mut path = self.path.clone();
+                    path.push(next_node.clone());
+                    return Some(path);
+                }
+                continue;
+            }
+            self.path.push(next_node.clone());
+            self.states.push(state);
+            self.next_edges.push(0);
+        }
+
+        None
     }
 }
 
@@ -115,18 +634,64 @@ fn parse_input(input: &str) -> Graph {
     graph
 }
 
-fn main() {
+fn main() -> anyhow::Result<()> {
     let graph = parse_input(include_str!("input.txt"));
-    let count = graph.count_all_paths(false);
+    if std::env::args().any(|arg| arg == "--dot") {
+        print!("{}", graph.to_dot());
+        return Ok(());
+    }
+    if graph.has_infinite_paths() {
+        println!("Infinite number of paths");
+        return Ok(());
+    }
+
+    let count = graph.count_all_paths(&SmallCaves::new(0))?;
+    dbg!(count);
+
+    let count = graph.count_all_paths(&SmallCaves::new(1))?;
     dbg!(count);
 
-    let count = graph.count_all_paths(true);
+    let count = graph.count_all_paths(&SmallCaves::new(2))?;
     dbg!(count);
+
+    // the first small cave may be visited three times, the last is closed and every cave
+    // that disconnects the system has to be passed
+    let small = graph
+        .caves
+        .iter()
+        .filter(|node| node.small() && !node.is_start() && !node.is_end())
+        .sorted()
+        .collect_vec();
+    if let [first, .., last] = small[..] {
+        let policy = VisitBudget::new(1)
+            .with_budget(first.clone(), 3)
+            .forbid([last.clone()])
+            .through(graph.articulation_points());
+        let count = graph.count_all_paths(&policy)?;
+        dbg!(count);
+    }
+
+    if let Some(path) = graph.shortest_path() {
+        println!("Shortest path: {}", path.iter().join(","));
+    }
+    if let Some(path) = graph
+        .paths(&SmallCaves::new(0))?
+        .max_by_key(|path| path.len())
+    {
+        println!("Longest path: {}", path.iter().join(","));
+    }
+    println!(
+        "Articulation points: {}",
+        graph.articulation_points().iter().join(",")
+    );
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::parse_input;
+    use itertools::Itertools;
+
+    use crate::{parse_input, Node, SmallCaves, VisitBudget, VisitPolicy};
 
     // dc, end, start, HN, kj, LN, sa
     const INPUT: &str = r#"
@@ -143,7 +708,7 @@ mod tests {
     "#;
 
     #[test]
-    fn traverse_minimal_graph() {
+    fn traverse_minimal_graph() -> anyhow::Result<()> {
         let input = r#"
             start-A
             start-b
@@ -154,14 +719,205 @@ mod tests {
             b-end
         "#;
         let graph = parse_input(input);
-        assert_eq!(10, graph.count_all_paths(false));
-        assert_eq!(36, graph.count_all_paths(true));
+        assert_eq!(Some(10), graph.count_all_paths(&SmallCaves::new(0))?);
+        assert_eq!(Some(36), graph.count_all_paths(&SmallCaves::new(1))?);
+        Ok(())
+    }
+
+    #[test]
+    fn traverses_and_counts_all_paths() -> anyhow::Result<()> {
+        let graph = parse_input(INPUT);
+        assert_eq!(Some(19), graph.count_all_paths(&SmallCaves::new(0))?);
+        assert_eq!(Some(103), graph.count_all_paths(&SmallCaves::new(1))?);
+        Ok(())
+    }
+
+    #[test]
+    fn iterates_paths_lazily() -> anyhow::Result<()> {
+        let graph = parse_input(INPUT);
+        assert_eq!(19, graph.paths(&SmallCaves::new(0))?.count());
+        assert_eq!(103, graph.paths(&SmallCaves::new(1))?.count());
+
+        let paths = graph.paths(&SmallCaves::new(1))?.collect_vec();
+        assert_eq!(paths.len(), paths.iter().unique().count());
+        for path in paths.iter() {
+            assert_eq!(Some(&Node::new("start".to_string())), path.first());
+            assert_eq!(Some(&Node::new("end".to_string())), path.last());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn counts_paths_of_larger_graph() -> anyhow::Result<()> {
+        let input = r#"
+            fs-end
+            he-DX
+            fs-he
+            start-DX
+            pj-DX
+            end-zg
+            zg-sl
+            zg-pj
+            pj-he
+            RW-he
+            fs-DX
+            pj-RW
+            zg-RW
+            start-pj
+            he-WI
+            zg-he
+            pj-fs
+            start-RW
+        "#;
+        let graph = parse_input(input);
+        assert_eq!(Some(226), graph.count_all_paths(&SmallCaves::new(0))?);
+        assert_eq!(Some(3509), graph.count_all_paths(&SmallCaves::new(1))?);
+        assert_eq!(3509, graph.paths(&SmallCaves::new(1))?.count());
+        Ok(())
     }
 
     #[test]
-    fn traverses_and_counts_all_paths() {
+    fn applies_visit_policies() -> anyhow::Result<()> {
         let graph = parse_input(INPUT);
-        assert_eq!(19, graph.count_all_paths(false));
-        assert_eq!(103, graph.count_all_paths(true));
+        let cave = |name: &str| Node::new(name.to_string());
+
+        // a budget of one visit per small cave is the same as visiting them once
+        assert_eq!(Some(19), graph.count_all_paths(&VisitBudget::new(1))?);
+        assert_eq!(Some(247), graph.count_all_paths(&SmallCaves::new(2))?);
+        assert_eq!(247, graph.paths(&SmallCaves::new(2))?.count());
+
+        let policy = SmallCaves::new(0).forbid([cave("HN")]);
+        assert!(graph
+            .paths(&policy)?
+            .all(|path| !path.contains(&cave("HN"))));
+        assert_eq!(Some(2), graph.count_all_paths(&policy)?);
+        assert_eq!(2, graph.paths(&policy)?.count());
+
+        let policy = SmallCaves::new(2).through([cave("sa"), cave("LN")]);
+        let paths = graph.paths(&policy)?.collect_vec();
+        assert_eq!(12, paths.len());
+        assert!(paths
+            .iter()
+            .all(|path| path.contains(&cave("sa")) && path.contains(&cave("LN"))));
+        assert_eq!(Some(12), graph.count_all_paths(&policy)?);
+
+        // forbidding nothing changes nothing
+        assert_eq!(
+            Some(19),
+            graph.count_all_paths(&VisitBudget::new(1).forbid([]))?
+        );
+
+        let policy = VisitBudget::new(1).with_budget(cave("kj"), 2);
+        assert!(graph.paths(&policy)?.all(|path| path
+            .iter()
+            .filter(|&node| node == &cave("kj"))
+            .count()
+            <= 2));
+        assert_eq!(Some(55), graph.count_all_paths(&policy)?);
+        assert_eq!(55, graph.paths(&policy)?.count());
+        Ok(())
+    }
+
+    #[test]
+    fn visits_distinct_small_caves_twice() -> anyhow::Result<()> {
+        let graph = parse_input("start-A\nA-b\nA-end");
+        assert_eq!(Some(2), graph.count_all_paths(&SmallCaves::new(0))?);
+        assert_eq!(Some(3), graph.count_all_paths(&SmallCaves::new(1))?);
+
+        // `b` may not be visited a third time, even though a second cave could be doubled
+        let paths = graph.paths(&SmallCaves::new(2))?.collect_vec();
+        assert_eq!(3, paths.len());
+        assert_eq!(
+            "(start),(A),(b),(A),(b),(A),(end)",
+            paths
+                .iter()
+                .max_by_key(|path| path.len())
+                .unwrap()
+                .iter()
+                .join(",")
+        );
+        assert_eq!(Some(3), graph.count_all_paths(&SmallCaves::new(2))?);
+        Ok(())
+    }
+
+    #[test]
+    fn finds_shortest_path_and_articulation_points() {
+        let graph = parse_input(INPUT);
+        let path = graph.shortest_path().unwrap();
+        assert_eq!("(start),(HN),(end)", path.iter().join(","));
+
+        let caves = graph.articulation_points();
+        assert_eq!("(dc),(kj)", caves.iter().join(","));
+        assert!(!graph.has_infinite_paths());
+    }
+
+    #[test]
+    fn detects_infinite_paths() -> anyhow::Result<()> {
+        let graph = parse_input(
+            r#"
+            start-A
+            A-B
+            B-end
+            "#,
+        );
+        assert!(graph.has_infinite_paths());
+        assert_eq!(None, graph.count_all_paths(&SmallCaves::new(1))?);
+
+        // limiting the big caves makes the count finite again
+        let cave = |name: &str| Node::new(name.to_string());
+        let policy = VisitBudget::new(1)
+            .with_budget(cave("A"), 2)
+            .with_budget(cave("B"), 2);
+        assert_eq!(Some(2), graph.count_all_paths(&policy)?);
+
+        // a loop that never reaches the end does not matter
+        let graph = parse_input(
+            r#"
+            start-a
+            a-end
+            start-C
+            C-D
+            "#,
+        );
+        assert!(!graph.has_infinite_paths());
+        assert_eq!(Some(1), graph.count_all_paths(&SmallCaves::new(0))?);
+        Ok(())
+    }
+
+    #[test]
+    fn counts_paths_through_many_caves() -> anyhow::Result<()> {
+        let input = (0..70)
+            .map(|i| format!("start-B{}\nB{}-end", i, i))
+            .join("\n");
+        let graph = parse_input(&input);
+        assert_eq!(Some(70), graph.count_all_paths(&SmallCaves::new(1))?);
+        assert_eq!(70, graph.paths(&SmallCaves::new(0))?.count());
+
+        // small caves beyond the bit mask are rejected, but can still be counted with a budget
+        let input = (0..70)
+            .map(|i| format!("start-b{}\nb{}-end", i, i))
+            .join("\n");
+        let graph = parse_input(&input);
+        assert!(graph.count_all_paths(&SmallCaves::new(0)).is_err());
+        assert!(graph.paths(&SmallCaves::new(0)).is_err());
+        assert_eq!(Some(70), graph.count_all_paths(&VisitBudget::new(1))?);
+        assert!(!graph.has_infinite_paths());
+        Ok(())
+    }
+
+    #[test]
+    fn exports_dot() {
+        let graph = parse_input("start-A\nA-b\nb-end");
+        let expected = r#"graph caves {
+    "A" [shape=box, style=filled, fillcolor=lightgrey];
+    "b" [shape=ellipse];
+    "end" [shape=doublecircle];
+    "start" [shape=doublecircle];
+    "A" -- "b";
+    "A" -- "start";
+    "b" -- "end";
+}
+"#;
+        assert_eq!(expected, graph.to_dot());
     }
 }
@@ -1,7 +1,36 @@
-use std::fmt::Display;
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
 
 use itertools::Itertools;
 
+/// The width of a single letter in dots, letters are separated by an empty column
+const LETTER_WIDTH: u16 = 4;
+const LETTER_HEIGHT: u16 = 6;
+
+/// The block letters of the activation code, row by row
+const FONT: [(char, &str); 17] = [
+    ('A', ".##.#..##..######..##..#"),
+    ('B', "###.#..####.#..##..####."),
+    ('C', ".##.#..##...#...#..#.##."),
+    ('E', "#####...###.#...#...####"),
+    ('F', "#####...###.#...#...#..."),
+    ('G', ".##.#..##...#.###..#.###"),
+    ('H', "#..##..######..##..##..#"),
+    ('I', ".###..#...#...#...#..###"),
+    ('J', "..##...#...#...##..#.##."),
+    ('K', "#..##.#.##..#.#.#.#.#..#"),
+    ('L', "#...#...#...#...#...####"),
+    ('O', ".##.#..##..##..##..#.##."),
+    ('P', "###.#..##..####.#...#..."),
+    ('R', "###.#..##..####.#.#.#..#"),
+    ('S', ".####...#....##....####."),
+    ('U', "#..##..##..##..##..#.##."),
+    ('Z', "####...#..#..#..#...####"),
+];
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 struct Point {
     pub x: u16,
@@ -26,12 +55,17 @@ impl From<&str> for Point {
 enum Fold {
     Horizontal(u16),
     Vertical(u16),
+    /// Folds along the diagonal `y=x`, moving the dots below it above it
+    Diagonal,
 }
 
 impl From<&str> for Fold {
     fn from(line: &str) -> Self {
         let (_, instruction) = line.rsplit_once(" ").expect("Failed to split line.");
         let (axis, value) = instruction.split_once("=").expect("Failed to split fold");
+        if matches!((axis, value), ("y", "x") | ("x", "y")) {
+            return Fold::Diagonal;
+        }
         let value = value.parse::<u16>().unwrap();
 
         if axis == "y" {
@@ -51,11 +85,11 @@ struct Sheet {
 
 impl Display for Sheet {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let points = self.points.iter().collect::<HashSet<_>>();
         for y in 0..=self.max.y {
             let line = (0..=self.max.x)
-                .into_iter()
                 .map(|x| {
-                    if self.points.contains(&Point::new(x, y)) {
+                    if points.contains(&Point::new(x, y)) {
                         '#'
                     } else {
                         '.'
@@ -82,28 +116,67 @@ impl Sheet {
         }
     }
 
-    /// Folds one half of sheet onto the other half
+    /// Folds the sheet along the next fold line
     ///
-    /// This maps the numbers from one axis back to the first half:
+    /// This maps the numbers beyond the line back onto the other side:
     ///
     /// 0  1  2  3  4  5  6 [7] 8  9 10 11 12 13 14
     /// 0  1  2  3  4  5  6  7  6  5  4  3  2  1  0
     ///
+    /// If the folded part is longer than the rest, it overhangs and all numbers are shifted
+    /// to start at 0 again:
+    ///
+    /// 0  1 [2] 3  4  5  6
+    /// 2  3  4  3  2  1  0
+    ///
     pub fn fold(&self) -> Self {
-        fn flip(val: u16, line: u16) -> u16 {
-            ((line as i32 + 1) - ((line as i32 + 1) - val as i32).abs()) as u16
+        /// Returns the number mirrored at the line and the new maximum, shifted by the overhang
+        fn flip(val: u16, line: u16, max: u16) -> (u16, u16) {
+            let (val, line, max) = (i32::from(val), i32::from(line), i32::from(max));
+            let shift = (max - 2 * line).max(0);
+            let val = if val > line { 2 * line - val } else { val };
+            ((val + shift) as u16, (line - 1 + shift).max(0) as u16)
         }
 
-        let max = match self.folds[0] {
-            Fold::Horizontal(y) => Point::new(self.max.x, y - 1),
-            Fold::Vertical(x) => Point::new(x - 1, self.max.y),
+        let (points, max) = match self.folds[0] {
+            Fold::Horizontal(y) => {
+                let points = self
+                    .points
+                    .iter()
+                    .map(|p| Point::new(p.x, flip(p.y, y, self.max.y).0))
+                    .collect_vec();
+                (points, Point::new(self.max.x, flip(0, y, self.max.y).1))
+            }
+            Fold::Vertical(x) => {
+                let points = self
+                    .points
+                    .iter()
+                    .map(|p| Point::new(flip(p.x, x, self.max.x).0, p.y))
+                    .collect_vec();
+                (points, Point::new(flip(0, x, self.max.x).1, self.max.y))
+            }
+            Fold::Diagonal => {
+                let points = self
+                    .points
+                    .iter()
+                    .map(|p| {
+                        if p.y > p.x {
+                            Point::new(p.y, p.x)
+                        } else {
+                            p.clone()
+                        }
+                    })
+                    .collect_vec();
+                let (min, max) = (self.max.x.min(self.max.y), self.max.x.max(self.max.y));
+                (points, Point::new(max, min))
+            }
         };
 
-        let points = self.points
+        // dots on the fold line stay where they are
+        let max = points
             .iter()
-            .map(|p| Point::new(flip(p.x, max.x), flip(p.y, max.y)))
-            .unique()
-            .collect_vec();
+            .fold(max, |max, p| Point::new(max.x.max(p.x), max.y.max(p.y)));
+        let points = points.into_iter().unique().collect_vec();
 
         Self {
             points,
@@ -113,7 +186,70 @@ impl Sheet {
     }
 
     pub fn fold_all(&self) -> Sheet {
-        self.folds.iter().fold(self.clone(), |result, _| result.fold())
+        self.fold_steps().last().expect("No sheet found")
+    }
+
+    /// Returns the sheet before the first fold followed by the sheet after every fold
+    pub fn fold_steps(&self) -> impl Iterator<Item = Sheet> {
+        std::iter::successors(Some(self.clone()), |sheet| {
+            (!sheet.folds.is_empty()).then(|| sheet.fold())
+        })
+    }
+
+    /// Reads the block letters on the sheet, unknown letters are returned as `?`
+    pub fn ocr(&self) -> String {
+        let letters = (self.max.x + 1).div_ceil(LETTER_WIDTH + 1);
+        (0..letters)
+            .map(|letter| {
+                let left = letter * (LETTER_WIDTH + 1);
+                let dots = (0..LETTER_HEIGHT)
+                    .cartesian_product(0..LETTER_WIDTH)
+                    .map(|(y, x)| {
+                        if self.points.contains(&Point::new(left + x, y)) {
+                            '#'
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect::<String>();
+
+                FONT.iter()
+                    .find(|(_, glyph)| *glyph == dots)
+                    .map_or('?', |&(c, _)| c)
+            })
+            .collect()
+    }
+
+    /// Writes the sheet of every fold step as a PBM image into the directory, returns the
+    /// written files. Each dot is drawn as a square of `scale` pixels.
+    pub fn export_pbm(&self, dir: &Path, scale: u16) -> std::io::Result<Vec<PathBuf>> {
+        std::fs::create_dir_all(dir)?;
+
+        self.fold_steps()
+            .enumerate()
+            .map(|(step, sheet)| {
+                let (width, height) = (sheet.max.x + 1, sheet.max.y + 1);
+                let points = sheet.points.iter().collect::<HashSet<_>>();
+                let mut image = format!("P1\n{} {}\n", width * scale, height * scale);
+                for y in 0..height * scale {
+                    let row = (0..width * scale)
+                        .map(|x| {
+                            if points.contains(&Point::new(x / scale, y / scale)) {
+                                '1'
+                            } else {
+                                '0'
+                            }
+                        })
+                        .join(" ");
+                    image += &row;
+                    image.push('\n');
+                }
+
+                let path = dir.join(format!("fold_{:02}.pbm", step));
+                std::fs::write(&path, image)?;
+                Ok(path)
+            })
+            .collect()
     }
 }
 
@@ -140,17 +276,32 @@ fn parse_input(input: &str) -> Sheet {
 
 fn main() {
     let sheet = parse_input(include_str!("input.txt"));
-    let sheet = sheet.fold();
-    dbg!(sheet.points.len());
+    dbg!(sheet.fold().points.len());
+
+    // `--steps` prints the sheet after every fold, `--export <dir>` writes them as images
+    let args = std::env::args().collect_vec();
+    if args.iter().any(|arg| arg == "--steps") {
+        for (step, sheet) in sheet.fold_steps().enumerate().skip(1) {
+            println!("FOLD {}:\n{}", step, sheet);
+        }
+    }
+    if let Some(dir) = args.iter().skip_while(|&arg| arg != "--export").nth(1) {
+        let files = sheet
+            .export_pbm(Path::new(dir), 4)
+            .expect("Failed to export folds");
+        println!("Exported {} folds to {}", files.len(), dir);
+    }
 
-    // we need to print in order to read the 8 letters
     let sheet = sheet.fold_all();
     println!("FINAL:\n{}", sheet);
+    println!("CODE: {}", sheet.ocr());
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{parse_input, Fold, Point};
+    use itertools::Itertools;
+
+    use crate::{parse_input, Fold, Point, Sheet, FONT, LETTER_WIDTH};
 
     const INPUT: &str = r#"
         6,10
@@ -192,4 +343,100 @@ mod tests {
         assert_eq!(17, sheet.points.len());
         assert_eq!(vec![Fold::Vertical(5)], sheet.folds);
     }
+
+    #[test]
+    fn fold_steps() {
+        let sheet = parse_input(INPUT);
+        let steps = sheet.fold_steps().collect_vec();
+        assert_eq!(3, steps.len());
+        assert_eq!(18, steps[0].points.len());
+        assert_eq!(17, steps[1].points.len());
+        assert_eq!(16, steps[2].points.len());
+        assert_eq!(steps[2].points, sheet.fold_all().points);
+    }
+
+    #[test]
+    fn reads_letters() {
+        // writes all letters of the font next to each other
+        let points = FONT
+            .iter()
+            .enumerate()
+            .flat_map(|(letter, (_, glyph))| {
+                glyph
+                    .chars()
+                    .enumerate()
+                    .filter(|&(_, c)| c == '#')
+                    .map(move |(index, _)| {
+                        let x = letter as u16 * (LETTER_WIDTH + 1) + index as u16 % LETTER_WIDTH;
+                        Point::new(x, index as u16 / LETTER_WIDTH)
+                    })
+            })
+            .collect_vec();
+        let sheet = Sheet::new(points, Vec::new());
+        assert_eq!("ABCEFGHIJKLOPRSUZ", sheet.ocr());
+
+        // a single S, drawn row by row
+        let points = [
+            ".###", //
+            "#...", //
+            "#...", //
+            ".##.", //
+            "...#", //
+            "###.",
+        ]
+        .iter()
+        .enumerate()
+        .flat_map(|(y, row)| {
+            row.chars()
+                .enumerate()
+                .filter(|&(_, c)| c == '#')
+                .map(move |(x, _)| Point::new(x as u16, y as u16))
+        })
+        .collect_vec();
+        assert_eq!("S", Sheet::new(points, Vec::new()).ocr());
+
+        // the folded example is a square, which is not a letter
+        let sheet = parse_input(INPUT).fold_all();
+        assert_eq!("?", sheet.ocr());
+    }
+
+    #[test]
+    fn fold_off_centre() {
+        // the lower part overhangs and shifts all dots down
+        let sheet = parse_input("0,0\n1,1\n0,6\n1,5\nfold along y=2");
+        let sheet = sheet.fold();
+        assert_eq!(Point::new(1, 3), sheet.max);
+        assert_eq!(
+            vec![
+                Point::new(0, 2),
+                Point::new(1, 3),
+                Point::new(0, 0),
+                Point::new(1, 1)
+            ],
+            sheet.points
+        );
+
+        // dots beyond twice the line do not underflow
+        let sheet = parse_input("0,0\n10,0\nfold along x=3").fold();
+        assert_eq!(Point::new(6, 0), sheet.max);
+        assert_eq!(vec![Point::new(4, 0), Point::new(0, 0)], sheet.points);
+
+        // a short folded part lands within the rest
+        let sheet = parse_input("0,0\n0,9\nfold along y=8").fold();
+        assert_eq!(Point::new(0, 7), sheet.max);
+        assert_eq!(vec![Point::new(0, 0), Point::new(0, 7)], sheet.points);
+    }
+
+    #[test]
+    fn fold_diagonal() {
+        let sheet = parse_input("1,3\n3,1\n0,2\n4,0\nfold along y=x");
+        assert_eq!(vec![Fold::Diagonal], sheet.folds);
+
+        let sheet = sheet.fold();
+        assert_eq!(Point::new(4, 3), sheet.max);
+        assert_eq!(
+            vec![Point::new(3, 1), Point::new(2, 0), Point::new(4, 0)],
+            sheet.points
+        );
+    }
 }
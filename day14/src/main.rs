@@ -1,100 +1,728 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+    ops::{Add, Mul, Range, Sub},
+};
 
+use anyhow::{anyhow, bail};
 use itertools::Itertools;
 
-/// TODO replace String into a `Vec<u8>` ?
+/// A number type to count pairs and letters with
+trait Count: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> {
+    const ZERO: Self;
+    const ONE: Self;
+}
+
+impl Count for u64 {
+    const ZERO: Self = 0;
+    const ONE: Self = 1;
+}
+
+impl Count for u128 {
+    const ZERO: Self = 0;
+    const ONE: Self = 1;
+}
+
+/// Counts modulo `M`, for step counts where the exact numbers do not fit any integer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Modular<const M: u64>(pub u64);
+
+impl<const M: u64> Add for Modular<M> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self(((self.0 as u128 + rhs.0 as u128) % M as u128) as u64)
+    }
+}
+
+impl<const M: u64> Sub for Modular<M> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self(((self.0 as u128 + M as u128 - rhs.0 as u128) % M as u128) as u64)
+    }
+}
+
+impl<const M: u64> Mul for Modular<M> {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self(((self.0 as u128 * rhs.0 as u128) % M as u128) as u64)
+    }
+}
+
+impl<const M: u64> Count for Modular<M> {
+    const ZERO: Self = Self(0);
+    const ONE: Self = Self(1 % M);
+}
+
+/// An exact count that turns into `None` once it overflows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Checked(pub Option<u128>);
+
+impl Add for Checked {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self(
+            self.0
+                .zip(rhs.0)
+                .and_then(|(lhs, rhs)| lhs.checked_add(rhs)),
+        )
+    }
+}
+
+impl Sub for Checked {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self(
+            self.0
+                .zip(rhs.0)
+                .and_then(|(lhs, rhs)| lhs.checked_sub(rhs)),
+        )
+    }
+}
+
+impl Mul for Checked {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        // nothing times an overflowed count is still nothing
+        match (self.0, rhs.0) {
+            (Some(0), _) | (_, Some(0)) => Self::ZERO,
+            (lhs, rhs) => Self(lhs.zip(rhs).and_then(|(lhs, rhs)| lhs.checked_mul(rhs))),
+        }
+    }
+}
+
+impl Count for Checked {
+    const ZERO: Self = Self(Some(0));
+    const ONE: Self = Self(Some(1));
+}
+
+/// An approximate count `mantissa * 2^exponent`, its range is large enough to compare the
+/// letter counts of any number of steps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Approx {
+    mantissa: f64,
+    exponent: i64,
+}
+
+impl Approx {
+    /// The relative difference below which two counts may be ordered wrong by rounding errors
+    const TOLERANCE: f64 = 1e-9;
+
+    fn new(mantissa: f64, exponent: i64) -> Self {
+        if mantissa == 0.0 {
+            return Self::ZERO;
+        }
+        let shift = mantissa.abs().log2().floor();
+        Self {
+            mantissa: mantissa / shift.exp2(),
+            exponent: exponent + shift as i64,
+        }
+    }
+
+    /// Returns both mantissas scaled to the larger exponent
+    fn align(self, rhs: Self) -> (f64, f64, i64) {
+        let exponent = self.exponent.max(rhs.exponent);
+        let scale = |value: Self| {
+            let shift = (value.exponent - exponent).max(-1100) as i32;
+            value.mantissa * 2_f64.powi(shift)
+        };
+        (scale(self), scale(rhs), exponent)
+    }
+
+    /// Returns true if both counts are too close to tell them apart
+    fn is_close(self, other: Self) -> bool {
+        let (lhs, rhs, _) = self.align(other);
+        (lhs - rhs).abs() <= Self::TOLERANCE * lhs.abs().max(rhs.abs())
+    }
+}
+
+impl Add for Approx {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        let (lhs, rhs, exponent) = self.align(rhs);
+        Self::new(lhs + rhs, exponent)
+    }
+}
+
+impl Sub for Approx {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        let (lhs, rhs, exponent) = self.align(rhs);
+        Self::new(lhs - rhs, exponent)
+    }
+}
+
+impl Mul for Approx {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(self.mantissa * rhs.mantissa, self.exponent + rhs.exponent)
+    }
+}
+
+impl PartialOrd for Approx {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        let (lhs, rhs, _) = self.align(*other);
+        lhs.partial_cmp(&rhs)
+    }
+}
+
+impl Count for Approx {
+    const ZERO: Self = Self {
+        mantissa: 0.0,
+        exponent: 0,
+    };
+    const ONE: Self = Self {
+        mantissa: 1.0,
+        exponent: 0,
+    };
+}
+
+/// A square matrix of pair transitions, `cells[to * size + from]`
+#[derive(Debug, Clone)]
+struct Matrix<C> {
+    size: usize,
+    cells: Vec<C>,
+}
+
+impl<C: Count> Matrix<C> {
+    pub fn identity(size: usize) -> Self {
+        let mut cells = vec![C::ZERO; size * size];
+        for i in 0..size {
+            cells[i * size + i] = C::ONE;
+        }
+        Self { size, cells }
+    }
+
+    pub fn mul(&self, rhs: &Self) -> Self {
+        let size = self.size;
+        let mut cells = vec![C::ZERO; size * size];
+        for row in 0..size {
+            for k in 0..size {
+                let lhs = self.cells[row * size + k];
+                for col in 0..size {
+                    cells[row * size + col] =
+                        cells[row * size + col] + lhs * rhs.cells[k * size + col];
+                }
+            }
+        }
+        Self { size, cells }
+    }
+
+    /// Raises the matrix to the given power by repeated squaring
+    pub fn pow(&self, mut exponent: u64) -> Self {
+        let mut result = Self::identity(self.size);
+        let mut base = self.clone();
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = result.mul(&base);
+            }
+            base = base.mul(&base);
+            exponent >>= 1;
+        }
+        result
+    }
+
+    pub fn apply(&self, vector: &[C]) -> Vec<C> {
+        (0..self.size)
+            .map(|row| {
+                (0..self.size).fold(C::ZERO, |sum, col| {
+                    sum + self.cells[row * self.size + col] * vector[col]
+                })
+            })
+            .collect_vec()
+    }
+}
+
+/// Which insertion rules fired while stepping a polymer
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RuleReport {
+    /// Every rule that fired with the first step it fired in, starting at 1
+    pub fired: BTreeMap<[u8; 2], u64>,
+    /// All rules that never fired, sorted
+    pub unused: Vec<[u8; 2]>,
+}
+
+impl Display for RuleReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (pair, step) in self.fired.iter() {
+            writeln!(
+                f,
+                "{} fires in step {}",
+                String::from_utf8_lossy(pair),
+                step
+            )?;
+        }
+        for pair in self.unused.iter() {
+            writeln!(f, "{} never fires", String::from_utf8_lossy(pair))?;
+        }
+        Ok(())
+    }
+}
+
+/// The polymer after a number of steps, expanded on demand like a rope.
+///
+/// Every template pair expands into a tree of pairs, the length of every (pair, depth) is
+/// stored so queries only descend into the pairs covering the requested range. Depths stop
+/// being stored once their lengths grow by the same amount every depth, e.g. because all of
+/// them saturated or a rule keeps adding the same letter.
+#[derive(Debug, Clone)]
+struct Expansion {
+    alphabet: Vec<u8>,
+    transitions: Vec<Vec<usize>>,
+    /// The number of steps the polymer was expanded
+    steps: usize,
+    /// The expanded length of every pair index by depth, saturating at `u64::MAX`
+    lengths: Vec<Vec<u64>>,
+    /// The growth of every pair length per depth beyond the last stored one
+    growth: Vec<u64>,
+    /// The pair indices of the template
+    pairs: Vec<usize>,
+    last: Option<u8>,
+}
+
+impl Expansion {
+    /// Returns the expanded length of the pair at the given depth
+    fn length(&self, depth: usize, pair: usize) -> u64 {
+        let last = self.lengths.len() - 1;
+        if depth <= last {
+            return self.lengths[depth][pair];
+        }
+        let growth = self.growth[pair].saturating_mul((depth - last) as u64);
+        self.lengths[last][pair].saturating_add(growth)
+    }
+
+    /// Returns the length of the polymer
+    pub fn len(&self) -> u64 {
+        self.pairs
+            .iter()
+            .fold(u64::from(self.last.is_some()), |len, &pair| {
+                len.saturating_add(self.length(self.steps, pair))
+            })
+    }
+
+    /// Returns the letter at the given index
+    pub fn char_at(&self, index: u64) -> Option<char> {
+        let mut index = index;
+        for &pair in self.pairs.iter() {
+            let len = self.length(self.steps, pair);
+            if index < len {
+                return Some(self.char_in_pair(pair, self.steps, index));
+            }
+            index -= len;
+        }
+        (index == 0).then_some(self.last? as char)
+    }
+
+    /// Descends into the pair expansion containing the index
+    fn char_in_pair(&self, mut pair: usize, mut depth: usize, mut index: u64) -> char {
+        while index > 0 {
+            let [left, right] = self.transitions[pair][..] else {
+                break;
+            };
+            depth -= 1;
+            let len = self.length(depth, left);
+            if index < len {
+                pair = left;
+            } else {
+                pair = right;
+                index -= len;
+            }
+        }
+        self.alphabet[pair / self.alphabet.len()] as char
+    }
+
+    /// Returns the letters in the given range, clamped to the polymer
+    pub fn substring(&self, range: Range<u64>) -> String {
+        let mut result = String::new();
+        let mut offset = 0;
+        for &pair in self.pairs.iter() {
+            if offset >= range.end {
+                break;
+            }
+            self.collect(pair, self.steps, offset, &range, &mut result);
+            offset = offset.saturating_add(self.length(self.steps, pair));
+        }
+        if let Some(last) = self.last {
+            if range.contains(&offset) {
+                result.push(last as char);
+            }
+        }
+        result
+    }
+
+    /// Appends the letters of the pair expansion starting at `offset` that are within range
+    fn collect(
+        &self,
+        pair: usize,
+        depth: usize,
+        offset: u64,
+        range: &Range<u64>,
+        out: &mut String,
+    ) {
+        // descends depth first, the right halves wait on the stack until the left ones are done
+        let mut stack = vec![(pair, depth, offset)];
+        while let Some((pair, depth, offset)) = stack.pop() {
+            let end = offset.saturating_add(self.length(depth, pair));
+            if end <= range.start || offset >= range.end {
+                continue;
+            }
+            match self.transitions[pair][..] {
+                [left, right] if depth > 0 => {
+                    let middle = offset.saturating_add(self.length(depth - 1, left));
+                    if middle < range.end {
+                        stack.push((right, depth - 1, middle));
+                    }
+                    stack.push((left, depth - 1, offset));
+                }
+                _ => out.push(self.alphabet[pair / self.alphabet.len()] as char),
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Polymer {
-    pub template: String,
-    pub rules: HashMap<String, String>,
+    pub template: Vec<u8>,
+    pub rules: HashMap<[u8; 2], u8>,
 }
 
 impl Polymer {
-    pub fn new(template: &str, rules: &[(String, String)]) -> Self {
-        let rules = rules.iter().cloned().collect::<HashMap<String, String>>();
-        Self {
-            template: template.to_string(),
-            rules,
+    /// Creates a new polymer, fails if a pair has more than one rule
+    pub fn new(template: &str, rules: &[([u8; 2], u8)]) -> anyhow::Result<Self> {
+        let mut rule_map = HashMap::new();
+        for &(pair, c) in rules {
+            let pair_str = String::from_utf8_lossy(&pair);
+            match rule_map.insert(pair, c) {
+                Some(other) if other == c => {
+                    bail!("Duplicate rule {} -> {}", pair_str, c as char)
+                }
+                Some(other) => bail!(
+                    "Conflicting rules {} -> {} and {} -> {}",
+                    pair_str,
+                    other as char,
+                    pair_str,
+                    c as char
+                ),
+                None => (),
+            }
         }
+
+        Ok(Self {
+            template: template.as_bytes().to_vec(),
+            rules: rule_map,
+        })
     }
 
-    /// Processes the given number of steps, creates a resulting string with all insertions
-    /// after steps are processed.
-    ///
-    pub fn steps(&self, steps: usize) -> HashMap<String, usize> {
-        let mut pairs: HashMap<String, usize> = HashMap::new();
-        for (l, r) in self.template.chars().tuple_windows() {
-            *pairs.entry(format!("{}{}", l, r)).or_insert(0) += 1_usize;
+    /// Returns all letters in the template and rules, the index of a letter is its position
+    fn alphabet(&self) -> Vec<u8> {
+        self.template
+            .iter()
+            .chain(
+                self.rules
+                    .iter()
+                    .flat_map(|(pair, c)| pair.iter().chain([c])),
+            )
+            .copied()
+            .sorted()
+            .dedup()
+            .collect_vec()
+    }
+
+    /// Returns the dense index of the pair, given the alphabet
+    fn pair_index(alphabet: &[u8], l: u8, r: u8) -> usize {
+        let index = |c| alphabet.binary_search(&c).expect("Letter not in alphabet");
+        index(l) * alphabet.len() + index(r)
+    }
+
+    /// Returns for every pair index the pair indices it turns into after one step, a pair
+    /// without an insertion rule stays as it is.
+    fn transitions(&self, alphabet: &[u8]) -> Vec<Vec<usize>> {
+        alphabet
+            .iter()
+            .cartesian_product(alphabet.iter())
+            .map(|(&l, &r)| match self.rules.get(&[l, r]) {
+                Some(&c) => vec![
+                    Self::pair_index(alphabet, l, c),
+                    Self::pair_index(alphabet, c, r),
+                ],
+                None => vec![Self::pair_index(alphabet, l, r)],
+            })
+            .collect_vec()
+    }
+
+    /// Returns the number of every pair in the template, indexed by pair index
+    fn template_pairs<C: Count>(&self, alphabet: &[u8]) -> Vec<C> {
+        let mut pairs = vec![C::ZERO; alphabet.len() * alphabet.len()];
+        for (&l, &r) in self.template.iter().tuple_windows() {
+            let index = Self::pair_index(alphabet, l, r);
+            pairs[index] = pairs[index] + C::ONE;
         }
+        pairs
+    }
+
+    /// Counts the letters given the pair counts, every letter is the left side of a pair
+    /// except for the last one of the template.
+    fn letter_counts<C: Count>(&self, alphabet: &[u8], pairs: &[C]) -> HashMap<char, C> {
+        let mut counts: HashMap<char, C> = HashMap::new();
+        for (index, &count) in pairs.iter().enumerate() {
+            let l = alphabet[index / alphabet.len()] as char;
+            let entry = counts.entry(l).or_insert(C::ZERO);
+            *entry = *entry + count;
+        }
+        if let Some(&last) = self.template.last() {
+            let entry = counts.entry(last as char).or_insert(C::ZERO);
+            *entry = *entry + C::ONE;
+        }
+        counts
+    }
+
+    /// Processes the given number of steps one by one, then counts the letters of the
+    /// resulting polymer.
+    ///
+    pub fn steps<C: Count>(&self, steps: usize) -> HashMap<char, C> {
+        let alphabet = self.alphabet();
+        let transitions = self.transitions(&alphabet);
+        let mut pairs = self.template_pairs::<C>(&alphabet);
 
         for _ in 0..steps {
-            let mut pairs2 = HashMap::new();
-            for (pair, count) in pairs.iter() {
-                let (l, r) = pair.split_at(1);
-                let c = self.rules.get(pair).unwrap();
-                *pairs2.entry(format!("{}{}", l, c)).or_insert(0) += count;
-                *pairs2.entry(format!("{}{}", c, r)).or_insert(0) += count;
+            let mut next = vec![C::ZERO; pairs.len()];
+            for (&count, targets) in pairs.iter().zip(transitions.iter()) {
+                for &target in targets {
+                    next[target] = next[target] + count;
+                }
             }
+            pairs = next;
+        }
+
+        self.letter_counts(&alphabet, &pairs)
+    }
 
-            pairs = pairs2.clone();
+    /// Same as [`Polymer::steps`], but in O(log steps) by raising the pair transition matrix
+    /// to the power of steps.
+    pub fn steps_pow<C: Count>(&self, steps: u64) -> HashMap<char, C> {
+        let alphabet = self.alphabet();
+        let transitions = self.transitions(&alphabet);
+
+        let mut matrix = Matrix {
+            size: transitions.len(),
+            cells: vec![C::ZERO; transitions.len() * transitions.len()],
+        };
+        for (from, targets) in transitions.iter().enumerate() {
+            for &to in targets {
+                let cell = &mut matrix.cells[to * matrix.size + from];
+                *cell = *cell + C::ONE;
+            }
         }
 
-        // Calculate single letter frequencies
-        let mut counts: HashMap<String, usize> = HashMap::new();
-        for (pair, count) in pairs.iter() {
-            let (l, _r) = pair.split_at(1);
-            *counts.entry(l.to_string()).or_insert(0) += count;
+        let pairs = matrix.pow(steps).apply(&self.template_pairs(&alphabet));
+        self.letter_counts(&alphabet, &pairs)
+    }
+
+    /// Returns which rules fire within the given number of steps, starting at the template.
+    ///
+    /// Only tracks which pairs exist, so this stops as soon as a set of pairs shows up again.
+    pub fn rule_report(&self, steps: u64) -> RuleReport {
+        let alphabet = self.alphabet();
+        let transitions = self.transitions(&alphabet);
+        let mut present = self
+            .template_pairs::<u64>(&alphabet)
+            .into_iter()
+            .map(|count| count > 0)
+            .collect_vec();
+
+        let mut fired = BTreeMap::new();
+        let mut seen = HashSet::new();
+        for step in 0..steps {
+            // every later step repeats the steps since that set of pairs was seen
+            if !seen.insert(present.clone()) {
+                break;
+            }
+            let mut next = vec![false; present.len()];
+            for (index, _) in present.iter().enumerate().filter(|(_, &present)| present) {
+                let pair = [
+                    alphabet[index / alphabet.len()],
+                    alphabet[index % alphabet.len()],
+                ];
+                if self.rules.contains_key(&pair) {
+                    fired.entry(pair).or_insert(step + 1);
+                }
+                for &target in transitions[index].iter() {
+                    next[target] = true;
+                }
+            }
+            present = next;
         }
-        *counts.get_mut(&self.template.chars().last().unwrap().to_string()).unwrap() += 1;
 
-        counts
+        let unused = self
+            .rules
+            .keys()
+            .filter(|pair| !fired.contains_key(*pair))
+            .copied()
+            .sorted()
+            .collect_vec();
+        RuleReport { fired, unused }
+    }
+
+    /// Returns a lazy view of the polymer after the given number of steps
+    pub fn expand(&self, steps: usize) -> Expansion {
+        let alphabet = self.alphabet();
+        let transitions = self.transitions(&alphabet);
+
+        // a pair expands to its left letter and everything inserted up to its right letter
+        let mut lengths = vec![vec![1_u64; transitions.len()]];
+        let mut growth = vec![0; transitions.len()];
+        while lengths.len() <= steps {
+            let previous = lengths.last().expect("No lengths");
+            let row = transitions
+                .iter()
+                .map(|targets| match targets[..] {
+                    [left, right] => previous[left].saturating_add(previous[right]),
+                    _ => 1,
+                })
+                .collect_vec();
+            // lengths never shrink, once they grow by the same amount twice they always will
+            let delta = row.iter().zip(previous).map(|(r, p)| r - p).collect_vec();
+            let linear = delta == growth || delta.iter().all(|&d| d == 0);
+            growth = delta;
+            if linear {
+                break;
+            }
+            lengths.push(row);
+        }
+
+        let pairs = self
+            .template
+            .iter()
+            .tuple_windows()
+            .map(|(&l, &r)| Self::pair_index(&alphabet, l, r))
+            .collect_vec();
+        Expansion {
+            alphabet,
+            transitions,
+            steps,
+            lengths,
+            growth,
+            pairs,
+            last: self.template.last().copied(),
+        }
     }
 
     /// Runs the polymer process `steps` time, then counts the number of letter occurrences
     /// to calculate the final result:
     /// `most_common - least_common`
-    pub fn calculate(&self, steps: usize) -> usize {
-        let map = self.steps(steps);
-        println!("CALCULATE: {:?}", map);
-
-        let (lowest, highest) = map
-            .iter()
-            .minmax_by_key(|&(_, len)| len)
+    ///
+    /// The most and least common letters are determined with approximate counts, so this also
+    /// works for counts that only fit a [`Modular`] number. Letters whose approximate counts
+    /// are too close are ranked by their exact counts, which fails if those overflow.
+    pub fn calculate<C: Count>(&self, steps: u64) -> anyhow::Result<C> {
+        let approx = self.steps_pow::<Approx>(steps);
+        let (lowest, highest) = approx
+            .values()
+            .minmax_by(|l, r| l.partial_cmp(r).expect("Failed to compare counts"))
             .into_option()
-            .expect("Failed to get min max");
+            .ok_or_else(|| anyhow!("No letters to count"))?;
+        let near = |extreme: &Approx| {
+            approx
+                .iter()
+                .filter(|(_, count)| count.is_close(*extreme))
+                .map(|(&letter, _)| letter)
+                .collect_vec()
+        };
+        let (lowest, highest) = (near(lowest), near(highest));
 
-        highest.1 - lowest.1
+        let (lowest, highest) = if lowest.len() == 1 && highest.len() == 1 {
+            (lowest[0], highest[0])
+        } else {
+            let exact = self.steps_pow::<Checked>(steps);
+            let rank = |letters: Vec<char>| {
+                letters
+                    .into_iter()
+                    .map(|letter| match exact[&letter] {
+                        Checked(Some(count)) => Ok((count, letter)),
+                        Checked(None) => {
+                            bail!("Letter counts after {} steps are too close to rank", steps)
+                        }
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()
+            };
+            let lowest = rank(lowest)?.into_iter().min().expect("No lowest letter");
+            let highest = rank(highest)?.into_iter().max().expect("No highest letter");
+            (lowest.1, highest.1)
+        };
+
+        let counts = self.steps_pow::<C>(steps);
+        Ok(counts[&highest] - counts[&lowest])
     }
 }
 
-fn parse_input(input: &str) -> Polymer {
+fn parse_input(input: &str) -> anyhow::Result<Polymer> {
     let lines = input
         .lines()
         .map(str::trim)
         .filter(|&line| !line.is_empty())
         .collect_vec();
 
-    let template = lines[0];
-    let rules: Vec<(String, String)> = lines[1..]
+    let template = lines.first().ok_or_else(|| anyhow!("No template found"))?;
+    let rules: Vec<([u8; 2], u8)> = lines[1..]
         .iter()
         .map(|&line| {
-            let (rule, c) = line.split_once(" -> ").expect("Failed to parse");
-            (rule.to_string(), c.to_string())
+            let (rule, c) = line
+                .split_once(" -> ")
+                .ok_or_else(|| anyhow!("Failed to parse rule '{}'", line))?;
+            let rule = rule
+                .as_bytes()
+                .try_into()
+                .map_err(|_| anyhow!("Invalid pair in rule '{}'", line))?;
+            match c.as_bytes() {
+                &[c] => Ok((rule, c)),
+                _ => bail!("Invalid insertion in rule '{}'", line),
+            }
         })
-        .collect_vec();
+        .collect::<anyhow::Result<_>>()?;
 
     Polymer::new(template, &rules)
 }
 
-fn main() {
-    let polymer = parse_input(include_str!("input.txt"));
-    dbg!(polymer.calculate(10));
-    dbg!(polymer.calculate(40));
+fn main() -> anyhow::Result<()> {
+    let polymer = parse_input(include_str!("input.txt"))?;
+    print!("{}", polymer.rule_report(10));
+    let counts = polymer.steps::<u64>(10);
+    println!(
+        "Letters after 10 steps: {}",
+        counts
+            .iter()
+            .sorted()
+            .map(|(letter, count)| format!("{}={}", letter, count))
+            .join(", ")
+    );
+    dbg!(polymer.calculate::<u64>(10)?);
+    dbg!(polymer.calculate::<u64>(40)?);
+    dbg!(polymer.calculate::<Modular<1_000_000_007>>(1_000_000)?);
+
+    let expansion = polymer.expand(40);
+    println!(
+        "After 40 steps: {} letters, starting with {}, letter {} is {:?}",
+        expansion.len(),
+        expansion.substring(0..40),
+        expansion.len() / 2,
+        expansion.char_at(expansion.len() / 2)
+    );
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::parse_input;
+    use itertools::Itertools;
+
+    use crate::{parse_input, Approx, Modular};
 
     const INPUT: &str = r#"
         NNCB
@@ -119,20 +747,195 @@ mod tests {
 
     #[test]
     fn check_parse_input() {
-        let input = parse_input(INPUT);
-        assert_eq!(String::from("NNCB"), input.template);
+        let input = parse_input(INPUT).unwrap();
+        assert_eq!(b"NNCB".to_vec(), input.template);
         assert_eq!(16, input.rules.len());
     }
 
     #[test]
     fn test_calculate_first_half() {
-        let input = parse_input(INPUT);
-        assert_eq!(1588, input.calculate(10));
+        let input = parse_input(INPUT).unwrap();
+        assert_eq!(1588, input.calculate::<u64>(10).unwrap());
     }
 
     #[test]
     fn test_calculate_second_half() {
-        let input = parse_input(INPUT);
-        assert_eq!(2188189693529, input.calculate(40));
+        let input = parse_input(INPUT).unwrap();
+        assert_eq!(2188189693529, input.calculate::<u64>(40).unwrap());
+    }
+
+    #[test]
+    fn steps_match_matrix_power() {
+        let input = parse_input(INPUT).unwrap();
+        for steps in [0, 1, 10, 40] {
+            assert_eq!(
+                input.steps::<u64>(steps),
+                input.steps_pow::<u64>(steps as u64)
+            );
+        }
+        let counts = input.steps::<u64>(10);
+        assert_eq!(1749, counts[&'B']);
+        assert_eq!(161, counts[&'H']);
+    }
+
+    #[test]
+    fn calculates_many_steps() {
+        let input = parse_input(INPUT).unwrap();
+        let exact = input.calculate::<u128>(40).unwrap();
+        assert_eq!(
+            Modular::<1_000_000_007>((exact % 1_000_000_007) as u64),
+            input.calculate::<Modular<1_000_000_007>>(40).unwrap()
+        );
+
+        // the approximate counts still compare correctly after a million steps
+        let counts = input.steps_pow::<Approx>(1_000_000);
+        assert!(counts.values().all(|count| count <= &counts[&'B']));
+        assert!(counts.values().all(|count| count >= &counts[&'H']));
+
+        let counts = input.steps_pow::<Modular<1_000_000_007>>(1_000_000);
+        assert_eq!(
+            counts[&'B'] - counts[&'H'],
+            input
+                .calculate::<Modular<1_000_000_007>>(1_000_000)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn ranks_near_ties_exactly() {
+        // X ends up with 2^s + 1, Y with 2^s + 2 and Z with 2 letters
+        let input = parse_input("XXZYYZY\nXX -> X\nYY -> Y").unwrap();
+        let counts = input.steps_pow::<Approx>(60);
+        assert_eq!(counts[&'X'], counts[&'Y']);
+        assert_eq!(1 << 60, input.calculate::<u128>(60).unwrap());
+        assert_eq!(
+            Modular::<1_000_000_007>(((1_u128 << 60) % 1_000_000_007) as u64),
+            input.calculate::<Modular<1_000_000_007>>(60).unwrap()
+        );
+
+        let err = input.calculate::<Modular<1_000_000_007>>(200).unwrap_err();
+        assert_eq!(
+            "Letter counts after 200 steps are too close to rank",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn keeps_pairs_without_rules() {
+        let input = parse_input("NNCB\nNN -> C").unwrap();
+        let counts = input.steps::<u64>(2);
+        assert_eq!(input.steps_pow::<u64>(2), counts);
+        // NNCB -> NCNCB -> NCNCB
+        assert_eq!(2, counts[&'N']);
+        assert_eq!(2, counts[&'C']);
+        assert_eq!(1, counts[&'B']);
+    }
+
+    #[test]
+    fn detects_invalid_rules() {
+        let err = parse_input("NNCB\nNN -> C\nNC -> B\nNN -> C").unwrap_err();
+        assert_eq!("Duplicate rule NN -> C", err.to_string());
+
+        let err = parse_input("NNCB\nNN -> C\nNN -> B").unwrap_err();
+        assert_eq!("Conflicting rules NN -> C and NN -> B", err.to_string());
+
+        assert!(parse_input("NNCB\nNNC -> B").is_err());
+        assert!(parse_input("NNCB\nNN -> ").is_err());
+    }
+
+    #[test]
+    fn reports_fired_rules() {
+        let input = parse_input("NNCB\nNN -> C\nNC -> B\nCB -> H\nBB -> N\nHH -> N").unwrap();
+        let report = input.rule_report(1);
+        assert_eq!(
+            vec![*b"CB", *b"NC", *b"NN"],
+            report.fired.keys().copied().collect_vec()
+        );
+        assert_eq!(vec![*b"BB", *b"HH"], report.unused);
+
+        // NCNBCHB, afterwards no new pairs with rules show up
+        let report = input.rule_report(1_000);
+        assert_eq!(vec![*b"BB", *b"HH"], report.unused);
+        assert_eq!("CB fires in step 1\nNC fires in step 1\nNN fires in step 1\nBB never fires\nHH never fires\n", report.to_string());
+
+        let report = parse_input(INPUT).unwrap().rule_report(10);
+        assert!(report.unused.is_empty());
+        assert_eq!(16, report.fired.len());
+
+        // the pairs alternate between CA and CB forever, AA never shows up
+        let input = parse_input("CA\nCA -> B\nCB -> A\nAA -> C").unwrap();
+        let report = input.rule_report(u64::MAX);
+        assert_eq!(
+            "CA fires in step 1\nCB fires in step 2\nAA never fires\n",
+            report.to_string()
+        );
+    }
+
+    #[test]
+    fn expands_lazily() {
+        let input = parse_input(INPUT).unwrap();
+        let expected = [
+            "NNCB",
+            "NCNBCHB",
+            "NBCCNBBBCBHCB",
+            "NBBBCNCCNBBNBNBBCHBHHBCHB",
+            "NBBNBNBBCCNBCNCCNBBNBBNBBBNBBNBBCBHCBHHNHCBBCBHCB",
+        ];
+        for (steps, polymer) in expected.iter().enumerate() {
+            let expansion = input.expand(steps);
+            assert_eq!(polymer.len() as u64, expansion.len());
+            assert_eq!(*polymer, expansion.substring(0..expansion.len()));
+            let chars = (0..=expansion.len())
+                .map_while(|index| expansion.char_at(index))
+                .collect::<String>();
+            assert_eq!(*polymer, chars);
+        }
+
+        let expansion = input.expand(4);
+        assert_eq!("CCNBCN", expansion.substring(8..14));
+        assert_eq!("BHCB", expansion.substring(45..100));
+        assert_eq!("", expansion.substring(60..70));
+        assert_eq!(None, expansion.char_at(49));
+
+        let expansion = input.expand(10);
+        assert_eq!(3073, expansion.len());
+        let counts = input.steps::<u64>(10);
+        let text = expansion.substring(0..expansion.len());
+        assert_eq!(
+            counts[&'B'] as usize,
+            text.chars().filter(|&c| c == 'B').count()
+        );
+
+        // pairs without a rule stay as they are
+        let expansion = parse_input("NNCB\nNN -> C").unwrap().expand(3);
+        assert_eq!("NCNCB", expansion.substring(0..10));
+        assert_eq!(2, expansion.lengths.len());
+    }
+
+    #[test]
+    fn expands_beyond_saturated_lengths() {
+        let input = parse_input(INPUT).unwrap();
+        let expansion = input.expand(1_000_000);
+        assert_eq!(u64::MAX, expansion.len());
+        assert!(expansion.lengths.len() < 100);
+
+        // the leftmost pairs settle on NB, so the start of the polymer no longer changes
+        let prefix = input.expand(100).substring(0..64);
+        assert_eq!(prefix, expansion.substring(0..64));
+        assert_eq!(prefix.chars().nth(63), expansion.char_at(63));
+        assert_eq!(64, expansion.substring(u64::MAX - 64..u64::MAX).len());
+    }
+
+    #[test]
+    fn expands_linear_growth_without_storing_every_depth() {
+        // every step inserts one more A in front of the B
+        let input = parse_input("AB\nAB -> A").unwrap();
+        let expansion = input.expand(1_000_000);
+        assert!(expansion.lengths.len() < 10);
+        assert_eq!(1_000_002, expansion.len());
+        assert_eq!("AAA", expansion.substring(0..3));
+        assert_eq!("AAB", expansion.substring(999_999..1_000_010));
+        assert_eq!(Some('B'), expansion.char_at(1_000_001));
+        assert_eq!(input.expand(5).substring(0..10), "AAAAAAB");
     }
 }
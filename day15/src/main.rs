@@ -1,5 +1,10 @@
-use std::{cmp::Reverse, collections::{BinaryHeap, HashMap}, fmt::Display};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+    fmt::Display,
+};
 
+use anyhow::anyhow;
 use itertools::Itertools;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -12,66 +17,388 @@ impl Point {
     pub fn new(x: u32, y: u32) -> Self {
         Self { x, y }
     }
+
+    pub fn manhattan(&self, other: &Point) -> u32 {
+        self.x.abs_diff(other.x) + self.y.abs_diff(other.y)
+    }
+}
+
+/// A path through the grid, its cost is the risk of all entered fields
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Path {
+    pub points: Vec<Point>,
+    pub cost: u32,
+}
+
+/// Displays the grid with the fields of the path highlighted
+struct PathOverlay<'a> {
+    grid: &'a Grid,
+    path: HashSet<Point>,
+}
+
+impl<'a> Display for PathOverlay<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.grid.render(f, &self.path)
+    }
+}
+
+/// The directions to the neighbors of a field, straight ones first
+const DIRECTIONS: [(i32, i32); 8] = [
+    (-1, 0),
+    (1, 0),
+    (0, -1),
+    (0, 1),
+    (-1, -1),
+    (1, -1),
+    (-1, 1),
+    (1, 1),
+];
+
+/// How the pathfinder moves through the grid and what moves cost.
+///
+/// Entering a field costs its risk times the weight of the move, changing the direction
+/// additionally costs `turn_cost`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Movement {
+    /// The weight of moves to the 4 straight neighbors
+    pub straight: u32,
+    /// The weight of moves to the diagonal neighbors, `None` disallows diagonal moves
+    pub diagonal: Option<u32>,
+    pub turn_cost: u32,
+    /// Fields that can not be entered
+    pub impassable: HashSet<Point>,
+}
+
+impl Default for Movement {
+    fn default() -> Self {
+        Self {
+            straight: 1,
+            diagonal: None,
+            turn_cost: 0,
+            impassable: HashSet::new(),
+        }
+    }
+}
+
+impl Movement {
+    /// Allows diagonal moves, which cost `multiplier` times a straight move. Fails if that
+    /// weight does not fit.
+    pub fn with_diagonal(self, multiplier: u32) -> anyhow::Result<Self> {
+        let diagonal = self.straight.checked_mul(multiplier).ok_or_else(|| {
+            anyhow!(
+                "Diagonal weight {} * {} overflows",
+                self.straight,
+                multiplier
+            )
+        })?;
+        Ok(Self {
+            diagonal: Some(diagonal),
+            ..self
+        })
+    }
+
+    pub fn with_turn_cost(self, turn_cost: u32) -> Self {
+        Self { turn_cost, ..self }
+    }
+
+    pub fn with_impassable(mut self, points: impl IntoIterator<Item = Point>) -> Self {
+        self.impassable.extend(points);
+        self
+    }
+
+    /// Returns the number of directions to move to
+    fn directions(&self) -> usize {
+        if self.diagonal.is_some() {
+            8
+        } else {
+            4
+        }
+    }
+
+    /// Returns the weight of a move in the given direction
+    fn weight(&self, direction: usize) -> u32 {
+        if direction < 4 {
+            self.straight
+        } else {
+            self.diagonal.unwrap_or(u32::MAX)
+        }
+    }
+
+    /// Returns the lowest possible cost to move the distance, given the lowest risk. Returns
+    /// `None` if the cost does not fit.
+    fn estimate(&self, from: Point, to: Point, min_risk: u32) -> Option<u32> {
+        // every diagonal step replaces two straight steps of the Manhattan distance
+        let diagonals = from.x.abs_diff(to.x).min(from.y.abs_diff(to.y)) as u64;
+        let straight = self.straight as u64;
+        let saved = (2 * straight).saturating_sub(self.diagonal.map_or(u64::MAX, u64::from));
+        let steps =
+            straight.checked_mul(from.manhattan(&to) as u64)? - saved.checked_mul(diagonals)?;
+        u32::try_from(steps.checked_mul(min_risk as u64)?).ok()
+    }
 }
 
+/// A virtual risk map made of a tile repeated to the right and downwards.
+///
+/// Every repetition increases the risk by one, wrapping from 9 back to 1. Only the tile is
+/// stored, the risk of every field is calculated when needed.
 #[derive(Debug)]
 struct Grid {
-    pub fields: Vec<(Point, u8)>,
+    tile: Vec<u8>,
+    tile_width: u32,
+    tile_height: u32,
     pub width: u32,
     pub height: u32,
 }
 
 impl Display for Grid {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for points in self.fields.chunks(self.width as usize) {
-            let values = points.iter().map(|(_p, value)| value).join("");
-            writeln!(f, "{}", values)?;
-        }
-        Ok(())
+        self.render(f, &HashSet::new())
     }
 }
 
 impl Grid {
-    pub fn new(fields: Vec<(Point, u8)>) -> Self {
-        let width = fields.iter().max_by_key(|&(p, _)| p.x).unwrap().0.x as u32 + 1;
-        let height = fields.iter().max_by_key(|&(p, _)| p.y).unwrap().0.y as u32 + 1;
+    /// Creates a grid of a single tile given its risk values row by row
+    pub fn new(tile: Vec<u8>, tile_width: u32) -> Self {
+        let tile_height = tile.len() as u32 / tile_width;
+        Self {
+            tile,
+            tile_width,
+            tile_height,
+            width: tile_width,
+            height: tile_height,
+        }
+    }
 
+    /// Repeats the tile the given number of times in both directions
+    pub fn repeat(self, repeat_x: u32, repeat_y: u32) -> Self {
         Self {
-            width,
-            height,
-            fields,
+            width: self.tile_width * repeat_x,
+            height: self.tile_height * repeat_y,
+            ..self
         }
     }
 
-    pub fn find_shortest_path(&self) -> u32 {
-        let (initial_node, _) = self.fields[0];
+    /// Returns all fields with their risk, row by row
+    pub fn fields(&self) -> impl Iterator<Item = (Point, u8)> + '_ {
+        (0..self.height)
+            .cartesian_product(0..self.width)
+            .map(|(y, x)| (Point::new(x, y), self.risk(Point::new(x, y))))
+    }
 
-        let mut best = self
-            .fields
-            .iter()
-            .cloned()
-            .map(|(point, _)| (point, u32::MAX))
-            .collect::<HashMap<_, _>>();
+    /// Writes all fields, the ones in `path` are highlighted in bold red
+    fn render(&self, f: &mut std::fmt::Formatter<'_>, path: &HashSet<Point>) -> std::fmt::Result {
+        for row in &self.fields().chunks(self.width as usize) {
+            let values = row
+                .map(|(p, value)| {
+                    if path.contains(&p) {
+                        format!("\x1b[1;31m{}\x1b[0m", value)
+                    } else {
+                        value.to_string()
+                    }
+                })
+                .join("");
+            writeln!(f, "{}", values)?;
+        }
+        Ok(())
+    }
+
+    /// Returns a view that displays the grid with the path highlighted
+    pub fn show_path(&self, path: &Path) -> PathOverlay<'_> {
+        PathOverlay {
+            grid: self,
+            path: path.points.iter().copied().collect(),
+        }
+    }
+
+    fn contains(&self, p: Point) -> bool {
+        p.x < self.width && p.y < self.height
+    }
+
+    /// Returns the risk of entering the field, increased by the number of tiles away from
+    /// the first one
+    pub fn risk(&self, p: Point) -> u8 {
+        let (tx, x) = (p.x / self.tile_width, p.x % self.tile_width);
+        let (ty, y) = (p.y / self.tile_height, p.y % self.tile_height);
+        let risk = self.tile[(y * self.tile_width + x) as usize];
+        Self::wrap(risk, tx + ty)
+    }
+
+    /// Increases the risk, wrapping from 9 back to 1
+    fn wrap(risk: u8, increase: u32) -> u8 {
+        1 + ((risk as u32 - 1 + increase) % 9) as u8
+    }
+
+    /// Returns the lowest risk of all fields
+    fn min_risk(&self) -> Option<u8> {
+        let tiles = (self.width / self.tile_width) + (self.height / self.tile_height) - 1;
+        (0..tiles.min(9))
+            .flat_map(|increase| {
+                self.tile
+                    .iter()
+                    .map(move |&risk| Self::wrap(risk, increase))
+            })
+            .min()
+    }
+
+    /// Returns all neighbors within the grid with the index of their direction
+    fn neighbors(&self, p: Point, directions: usize) -> impl Iterator<Item = (usize, Point)> + '_ {
+        DIRECTIONS
+            .into_iter()
+            .take(directions)
+            .enumerate()
+            .filter_map(move |(direction, (dx, dy))| {
+                let x = p.x.checked_add_signed(dx)?;
+                let y = p.y.checked_add_signed(dy)?;
+                Some((direction, Point::new(x, y)))
+            })
+            .filter(|&(_, p)| self.contains(p))
+    }
+
+    /// Finds the path with the lowest cost from start to goal via A*, moving as given by the
+    /// movement model.
+    ///
+    /// The heuristic is the Manhattan distance times the lowest risk of all fields, every
+    /// step enters at least one field of that risk. With diagonal moves it takes the cheaper
+    /// diagonal steps into account. Returns `None` if start or goal are not within the grid
+    /// or if there is no passable path, fails if a cost does not fit into `u32`.
+    pub fn find_path(
+        &self,
+        start: Point,
+        goal: Point,
+        movement: &Movement,
+    ) -> anyhow::Result<Option<Path>> {
+        if !self.contains(start) || !self.contains(goal) {
+            return Ok(None);
+        }
+        if movement.impassable.contains(&start) || movement.impassable.contains(&goal) {
+            return Ok(None);
+        }
+        let Some(min_risk) = self.min_risk() else {
+            return Ok(None);
+        };
+        let overflow = |p: Point| anyhow!("Cost of the path to {:?} overflows", p);
+        let heuristic = |p: Point| {
+            movement
+                .estimate(p, goal, min_risk as u32)
+                .ok_or_else(|| overflow(p))
+        };
+
+        // with turn costs the cost depends on the direction a field was entered from, every
+        // field then has a slot per direction and one for the start without any direction
+        let slots = if movement.turn_cost > 0 {
+            movement.directions() + 1
+        } else {
+            1
+        };
+
+        let start_slot = slots - 1;
+        let index = |p: Point, slot: usize| {
+            (p.y as u64 * self.width as u64 + p.x as u64) * slots as u64 + slot as u64
+        };
+        let mut best = States::new(self.width as u64 * self.height as u64 * slots as u64);
+        best.insert(index(start, start_slot), (0, None));
 
         let mut points = BinaryHeap::new();
-        points.push((Reverse(0), initial_node));
-
-        while let Some((Reverse(cost), current)) = points.pop() {
-            if cost < best[&current] {
-                best.insert(current, cost);
-
-                for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
-                    let x = current.x as i32 + dx;
-                    let y = current.y as i32 + dy;
-                    if 0 <= y && y < self.height as i32 && 0 <= x && x < self.width as i32 {
-                        let (neighbor, value) = self.fields[(y * self.width as i32 + x) as usize];
-                        points.push((Reverse(cost + value as u32), neighbor));
-                    }
+        points.push((Reverse(heuristic(start)?), Reverse(0), start, start_slot));
+
+        while let Some((_, Reverse(cost), current, slot)) = points.pop() {
+            if current == goal {
+                let mut path = vec![current];
+                let mut slot = slot;
+                let mut p = current;
+                while let Some((_, Some((direction, prev)))) = best.get(index(p, slot)) {
+                    // the stored directions always lead back into the grid
+                    let (dx, dy) = DIRECTIONS[direction as usize];
+                    p = Point::new(p.x.wrapping_add_signed(-dx), p.y.wrapping_add_signed(-dy));
+                    path.push(p);
+                    slot = prev as usize;
                 }
+                path.reverse();
+                return Ok(Some(Path { points: path, cost }));
+            }
+            if best
+                .get(index(current, slot))
+                .is_some_and(|(known, _)| cost > known)
+            {
+                continue;
             }
+
+            for (direction, neighbor) in self.neighbors(current, movement.directions()) {
+                if movement.impassable.contains(&neighbor) {
+                    continue;
+                }
+                let turned = slots > 1 && slot != start_slot && slot != direction;
+                let next = (self.risk(neighbor) as u32)
+                    .checked_mul(movement.weight(direction))
+                    .and_then(|weight| weight.checked_add(cost))
+                    .and_then(|next| next.checked_add(if turned { movement.turn_cost } else { 0 }))
+                    .ok_or_else(|| overflow(neighbor))?;
+                let next_slot = if slots > 1 { direction } else { 0 };
+                let next_index = index(neighbor, next_slot);
+                if best.get(next_index).is_none_or(|(known, _)| next < known) {
+                    best.insert(next_index, (next, Some((direction as u8, slot as u8))));
+                    points.push((
+                        Reverse(
+                            next.checked_add(heuristic(neighbor)?)
+                                .ok_or_else(|| overflow(neighbor))?,
+                        ),
+                        Reverse(next),
+                        neighbor,
+                        next_slot,
+                    ));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the lowest total risk from the top left to the bottom right corner
+    pub fn find_shortest_path(&self) -> anyhow::Result<u32> {
+        let goal = Point::new(self.width - 1, self.height - 1);
+        let path = self.find_path(Point::new(0, 0), goal, &Movement::default())?;
+        Ok(path.ok_or_else(|| anyhow!("No path to {:?}", goal))?.cost)
+    }
+}
+
+/// The best cost of a search state, with the direction it was entered from and the previous slot
+type State = (u32, Option<(u8, u8)>);
+
+/// The search states of [`Grid::find_path`] by the index `(y * width + x) * slots + slot`.
+///
+/// States are kept in a flat array like for a plain grid. Huge repeated grids would not fit
+/// into memory that way, there only the reached states are stored at the cost of hashing.
+enum States {
+    Dense(Vec<Option<State>>),
+    Sparse(HashMap<u64, State>),
+}
+
+impl States {
+    /// The largest number of states that is stored in a flat array
+    const DENSE_LIMIT: u64 = 1 << 24;
+
+    fn new(len: u64) -> Self {
+        if len <= Self::DENSE_LIMIT {
+            States::Dense(vec![None; len as usize])
+        } else {
+            States::Sparse(HashMap::new())
         }
+    }
 
-        best[&Point::new(self.width - 1, self.height - 1)]
+    fn get(&self, index: u64) -> Option<State> {
+        match self {
+            States::Dense(states) => states[index as usize],
+            States::Sparse(states) => states.get(&index).copied(),
+        }
+    }
+
+    fn insert(&mut self, index: u64, state: State) {
+        match self {
+            States::Dense(states) => states[index as usize] = Some(state),
+            States::Sparse(states) => {
+                states.insert(index, state);
+            }
+        }
     }
 }
 
@@ -79,6 +406,7 @@ fn parse_input(input: &str) -> Grid {
     parse_input_multiple(input, 1, 1)
 }
 
+/// Parses the tile and repeats it `repeat_x` times to the right and `repeat_y` times downwards
 fn parse_input_multiple(input: &str, repeat_x: u32, repeat_y: u32) -> Grid {
     let lines = input
         .lines()
@@ -86,41 +414,61 @@ fn parse_input_multiple(input: &str, repeat_x: u32, repeat_y: u32) -> Grid {
         .filter(|&line| !line.is_empty())
         .collect_vec();
 
-    let tile_height = lines.len() as u32;
     let tile_width = lines[0].len() as u32;
+    let tile = lines
+        .iter()
+        .flat_map(|line| line.chars())
+        .map(|c| c.to_digit(10).unwrap() as u8)
+        .collect_vec();
 
-    let mut fields = Vec::new();
-    for ry in 0..repeat_x {
-        for (y, &line) in lines.iter().enumerate() {
-            for rx in 0..repeat_y {
-                for (x, c) in line.chars().enumerate() {
-                    let px = rx * tile_width + x as u32;
-                    let py = ry * tile_height + y as u32;
-
-                    let digit = c.to_digit(10).unwrap() + rx + ry;
-                    let digit = 1 + ((digit as u8 - 1) % 9);
-                    fields.push((Point::new(px, py), digit));
-                }
-            }
-        }
-    }
-
-    Grid::new(fields)
+    Grid::new(tile, tile_width).repeat(repeat_x, repeat_y)
 }
 
-fn main() {
+fn main() -> anyhow::Result<()> {
     let grid = parse_input(include_str!("input.txt"));
-    let result = grid.find_shortest_path();
+    let result = grid.find_shortest_path()?;
     dbg!(result);
 
+    // shows the path through the middle of the grid
+    let center = Point::new(grid.width / 2, grid.height / 2);
+    if let Some(path) = grid.find_path(Point::new(0, 0), center, &Movement::default())? {
+        println!(
+            "Path to {:?} with risk {}:\n{}",
+            center,
+            path.cost,
+            grid.show_path(&path)
+        );
+    }
+
+    // avoids the highest risks, moves diagonally and prefers straight lines
+    let movement = Movement::default()
+        .with_diagonal(2)?
+        .with_turn_cost(5)
+        .with_impassable(grid.fields().filter(|&(_, risk)| risk == 9).map(|(p, _)| p));
+    let goal = Point::new(grid.width - 1, grid.height - 1);
+    if let Some(path) = grid.find_path(Point::new(0, 0), goal, &movement)? {
+        println!(
+            "Custom movement with cost {} in {} moves",
+            path.cost,
+            path.points.len() - 1
+        );
+    }
+
     let grid = parse_input_multiple(include_str!("input.txt"), 5, 5);
-    let result = grid.find_shortest_path();
+    let result = grid.find_shortest_path()?;
+    dbg!(result);
+
+    let grid = parse_input_multiple(include_str!("input.txt"), 20, 20);
+    let result = grid.find_shortest_path()?;
     dbg!(result);
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{parse_input, parse_input_multiple};
+    use itertools::Itertools;
+
+    use crate::{parse_input, parse_input_multiple, Movement, Point};
 
     const INPUT: &str = r#"
         1163751742
@@ -140,13 +488,14 @@ mod tests {
         let grid = parse_input(INPUT);
         assert_eq!(10, grid.width);
         assert_eq!(10, grid.height);
-        assert_eq!(100, grid.fields.len());
+        assert_eq!(100, grid.fields().count());
     }
 
     #[test]
-    fn find_shortest_path() {
+    fn find_shortest_path() -> anyhow::Result<()> {
         let grid = parse_input(INPUT);
-        assert_eq!(40, grid.find_shortest_path());
+        assert_eq!(40, grid.find_shortest_path()?);
+        Ok(())
     }
 
     #[test]
@@ -160,12 +509,204 @@ mod tests {
             34567
         "#;
         let expected = parse_input(expected);
-        assert_eq!(expected.fields, grid.fields);
+        assert_eq!(expected.fields().collect_vec(), grid.fields().collect_vec());
     }
 
     #[test]
-    fn find_shortest_path_2nd() {
+    fn find_shortest_path_2nd() -> anyhow::Result<()> {
         let grid = parse_input_multiple(INPUT, 5, 5);
-        assert_eq!(315, grid.find_shortest_path());
+        assert_eq!(315, grid.find_shortest_path()?);
+        Ok(())
+    }
+
+    #[test]
+    fn find_path_between_points() -> anyhow::Result<()> {
+        let grid = parse_input(INPUT);
+        let path = grid
+            .find_path(Point::new(0, 0), Point::new(9, 9), &Movement::default())?
+            .unwrap();
+        assert_eq!(40, path.cost);
+        assert_eq!(19, path.points.len());
+        assert_eq!(Some(&Point::new(0, 0)), path.points.first());
+        assert_eq!(Some(&Point::new(9, 9)), path.points.last());
+        let risk = path.points[1..]
+            .iter()
+            .map(|&p| grid.risk(p) as u32)
+            .sum::<u32>();
+        assert_eq!(40, risk);
+
+        // paths work in any direction
+        let path = grid
+            .find_path(Point::new(9, 9), Point::new(7, 9), &Movement::default())?
+            .unwrap();
+        assert_eq!(
+            vec![Point::new(9, 9), Point::new(8, 9), Point::new(7, 9)],
+            path.points
+        );
+        assert_eq!(13, path.cost);
+
+        let path = grid
+            .find_path(Point::new(3, 3), Point::new(3, 3), &Movement::default())?
+            .unwrap();
+        assert_eq!(0, path.cost);
+        assert_eq!(
+            None,
+            grid.find_path(Point::new(0, 0), Point::new(10, 0), &Movement::default())?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn display_path() -> anyhow::Result<()> {
+        let grid = parse_input("19\n11");
+        let path = grid
+            .find_path(Point::new(0, 0), Point::new(1, 1), &Movement::default())?
+            .unwrap();
+        assert_eq!(2, path.cost);
+        let red = |c| format!("\x1b[1;31m{}\x1b[0m", c);
+        assert_eq!(
+            format!("{}9\n{}{}\n", red(1), red(1), red(1)),
+            grid.show_path(&path).to_string()
+        );
+        assert_eq!("19\n11\n", grid.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn repeats_axes_independently() {
+        let grid = parse_input_multiple("18", 3, 2);
+        assert_eq!(6, grid.width);
+        assert_eq!(2, grid.height);
+        assert_eq!("182931\n293142\n", grid.to_string());
+    }
+
+    #[test]
+    fn find_shortest_path_in_large_repetition() -> anyhow::Result<()> {
+        let grid = parse_input_multiple(INPUT, 100, 100);
+        assert_eq!(1000, grid.width);
+        assert_eq!(Point::new(999, 999), grid.fields().last().unwrap().0);
+        assert_eq!(1, grid.risk(Point::new(999, 999)));
+
+        // the path leaves the 5x5 tiles of the second part for a lower risk
+        let goal = Point::new(49, 49);
+        let path = grid
+            .find_path(Point::new(0, 0), goal, &Movement::default())?
+            .unwrap();
+        assert_eq!(306, path.cost);
+        assert!(path.points.iter().any(|p| p.x > 49 || p.y > 49));
+        let small = parse_input_multiple(INPUT, 10, 10);
+        assert_eq!(
+            Some(path),
+            small.find_path(Point::new(0, 0), goal, &Movement::default())?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn find_path_in_huge_repetition() -> anyhow::Result<()> {
+        // ten billion fields, only the ones around the path are ever stored
+        let grid = parse_input_multiple(INPUT, 10_000, 10_000);
+        assert_eq!(100_000, grid.width);
+        let goal = Point::new(49, 49);
+        let path = grid
+            .find_path(Point::new(0, 0), goal, &Movement::default())?
+            .unwrap();
+        assert_eq!(306, path.cost);
+
+        let movement = Movement::default().with_turn_cost(5);
+        let path = grid.find_path(Point::new(0, 0), goal, &movement)?.unwrap();
+        assert_eq!(399, path.cost);
+        Ok(())
+    }
+
+    #[test]
+    fn moves_diagonally() -> anyhow::Result<()> {
+        let grid = parse_input(INPUT);
+        let (start, goal) = (Point::new(0, 0), Point::new(9, 9));
+
+        // diagonal moves with the same weight can only be cheaper
+        let path = grid
+            .find_path(start, goal, &Movement::default().with_diagonal(1).unwrap())?
+            .unwrap();
+        assert_eq!(20, path.cost);
+        let risk = path.points[1..]
+            .iter()
+            .map(|&p| grid.risk(p) as u32)
+            .sum::<u32>();
+        assert_eq!(risk, path.cost);
+        assert!(path
+            .points
+            .iter()
+            .tuple_windows()
+            .any(|(a, b)| a.x != b.x && a.y != b.y));
+
+        // diagonal moves that cost twice as much only pay off to skip a riskier field
+        let path = grid
+            .find_path(start, goal, &Movement::default().with_diagonal(2).unwrap())?
+            .unwrap();
+        assert_eq!(28, path.cost);
+
+        let straight = Movement {
+            straight: 10,
+            ..Movement::default()
+        };
+        let path = grid.find_path(start, goal, &straight)?.unwrap();
+        assert_eq!(400, path.cost);
+
+        let err = straight.with_diagonal(u32::MAX).unwrap_err();
+        assert_eq!("Diagonal weight 10 * 4294967295 overflows", err.to_string());
+
+        // the weight fits, but not the cost of entering a field with it
+        let movement = Movement::default().with_diagonal(u32::MAX)?;
+        let err = grid.find_path(start, goal, &movement).unwrap_err();
+        assert_eq!(
+            "Cost of the path to Point { x: 1, y: 1 } overflows",
+            err.to_string()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pays_for_turns() -> anyhow::Result<()> {
+        let grid = parse_input("111\n111\n111");
+        let (start, goal) = (Point::new(0, 0), Point::new(2, 2));
+        let path = grid.find_path(start, goal, &Movement::default())?.unwrap();
+        assert_eq!(4, path.cost);
+
+        // a single turn is the cheapest
+        let movement = Movement::default().with_turn_cost(10);
+        let path = grid.find_path(start, goal, &movement)?.unwrap();
+        assert_eq!(14, path.cost);
+        assert_eq!(5, path.points.len());
+        let turns = path
+            .points
+            .iter()
+            .tuple_windows()
+            .map(|(a, b)| (b.x as i32 - a.x as i32, b.y as i32 - a.y as i32))
+            .tuple_windows()
+            .filter(|(a, b)| a != b)
+            .count();
+        assert_eq!(1, turns);
+        Ok(())
+    }
+
+    #[test]
+    fn avoids_impassable_fields() -> anyhow::Result<()> {
+        let grid = parse_input("191\n111\n191");
+        let (start, goal) = (Point::new(0, 1), Point::new(2, 1));
+        let path = grid.find_path(start, goal, &Movement::default())?.unwrap();
+        assert_eq!(2, path.cost);
+
+        // walls off the middle, so the path needs to go around
+        let movement = Movement::default().with_impassable([Point::new(1, 1)]);
+        let path = grid.find_path(start, goal, &movement)?.unwrap();
+        assert_eq!(12, path.cost);
+        assert!(!path.points.contains(&Point::new(1, 1)));
+
+        let movement = movement.with_impassable([Point::new(1, 0), Point::new(1, 2)]);
+        assert_eq!(None, grid.find_path(start, goal, &movement)?);
+        let movement = Movement::default().with_impassable([goal]);
+        assert_eq!(None, grid.find_path(start, goal, &movement)?);
+        Ok(())
     }
 }
@@ -1,7 +1,21 @@
-use std::{fmt::Display, ops::Shl};
+use std::{
+    fmt::Display,
+    io::{BufRead, BufReader, Bytes, Cursor, Read},
+    ops::Shl,
+};
 
+use anyhow::{anyhow, bail, Context};
 use itertools::Itertools;
 
+/// How the length of the sub packets of an operator is encoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LengthType {
+    /// The total number of bits of all sub packets, type 0
+    Bits,
+    /// The number of sub packets, type 1
+    Count,
+}
+
 #[derive(Debug, PartialEq)]
 enum OperatorType {
     Sum,
@@ -30,8 +44,9 @@ impl From<u16> for OperatorType {
 
 #[derive(Debug, PartialEq)]
 struct Operator {
-    pub packets: Box<Vec<Packet>>,
+    pub packets: Vec<Packet>,
     pub kind: OperatorType,
+    pub length_type: LengthType,
 }
 
 impl Operator {
@@ -41,42 +56,90 @@ impl Operator {
             .map(|packet| packet.count_version())
             .sum::<usize>()
     }
+}
 
-    pub fn calculate(&self) -> u64 {
-        let iter = self.packets.iter();
-        match self.kind {
-            OperatorType::Sum => iter.map(|p| p.calculate()).sum::<u64>(),
-            OperatorType::Product => iter.fold(1_u64, |product, p| product * p.calculate()),
-            OperatorType::Min => iter.map(|p| p.calculate()).min().unwrap_or(0),
-            OperatorType::Max => iter.map(|p| p.calculate()).max().unwrap_or(0),
-            OperatorType::GreaterThan => {
-                if self.packets[0].calculate() > self.packets[1].calculate() {
-                    1
-                } else {
-                    0
-                }
-            },
-            OperatorType::LessThan => {
-                if self.packets[0].calculate() < self.packets[1].calculate() {
-                    1
-                } else {
-                    0
-                }
+/// The precedence of literals and function calls like `max(1, 2)` in expressions
+const ATOM: u8 = 3;
+
+impl OperatorType {
+    /// Returns the name of the operator when written as function
+    pub fn name(&self) -> &'static str {
+        match self {
+            OperatorType::Sum => "sum",
+            OperatorType::Product => "product",
+            OperatorType::Min => "min",
+            OperatorType::Max => "max",
+            OperatorType::GreaterThan => "gt",
+            OperatorType::LessThan => "lt",
+            OperatorType::Equal => "eq",
+        }
+    }
+
+    /// Returns the symbol of infix operators
+    pub fn symbol(&self) -> Option<&'static str> {
+        match self {
+            OperatorType::Sum => Some("+"),
+            OperatorType::Product => Some("*"),
+            OperatorType::GreaterThan => Some(">"),
+            OperatorType::LessThan => Some("<"),
+            OperatorType::Equal => Some("=="),
+            OperatorType::Min | OperatorType::Max => None,
+        }
+    }
+
+    /// Returns how tight the operator binds in expressions, higher binds tighter
+    fn precedence(&self) -> u8 {
+        match self {
+            OperatorType::GreaterThan | OperatorType::LessThan | OperatorType::Equal => 0,
+            OperatorType::Sum => 1,
+            OperatorType::Product => 2,
+            OperatorType::Min | OperatorType::Max => ATOM,
+        }
+    }
+
+    /// Applies the operator to the values of its sub packets, fails on overflow
+    pub fn apply(&self, values: &[u64]) -> anyhow::Result<u64> {
+        match self {
+            OperatorType::Sum => values
+                .iter()
+                .try_fold(0_u64, |sum, &value| sum.checked_add(value))
+                .ok_or_else(|| anyhow!("Overflow in sum of {}", values.iter().join(" + "))),
+            OperatorType::Product => values
+                .iter()
+                .try_fold(1_u64, |product, &value| product.checked_mul(value))
+                .ok_or_else(|| anyhow!("Overflow in product of {}", values.iter().join(" * "))),
+            OperatorType::Min => Ok(values.iter().copied().min().unwrap_or(0)),
+            OperatorType::Max => Ok(values.iter().copied().max().unwrap_or(0)),
+            comparison => {
+                let &[l, r] = values else {
+                    bail!("Expected 2 packets to compare, found {}", values.len());
+                };
+                let result = match comparison {
+                    OperatorType::GreaterThan => l > r,
+                    OperatorType::LessThan => l < r,
+                    _ => l == r,
+                };
+                Ok(u64::from(result))
             }
-            OperatorType::Equal => {
-                if self.packets[0].calculate() == self.packets[1].calculate() {
-                    1
-                } else {
-                    0
-                }
-            },
+        }
+    }
+
+    /// Writes the operator applied to the values, e.g. `2 + 5` or `max(3, 7)`
+    fn format(&self, values: &[u64]) -> String {
+        match self.symbol() {
+            Some(symbol) if values.len() >= 2 => values.iter().join(&format!(" {} ", symbol)),
+            _ => format!("{}({})", self.name(), values.iter().join(", ")),
         }
     }
 }
 
 #[derive(Debug, PartialEq)]
 enum PacketType {
-    Literal(u64),
+    /// A value and the number of 4 bit groups it was written in
+    Literal {
+        value: u64,
+        groups: usize,
+    },
     Operator(Operator),
 }
 
@@ -88,11 +151,13 @@ struct Packet {
 }
 
 impl Packet {
-    pub fn literal(version: u16, type_id: u16, literal: u64) -> Self {
+    /// Creates a literal packet written in the given number of 4 bit groups, which may
+    /// include leading zero groups
+    pub fn literal_in_groups(version: u16, type_id: u16, value: u64, groups: usize) -> Self {
         Self {
             version,
             type_id,
-            data: PacketType::Literal(literal),
+            data: PacketType::Literal { value, groups },
         }
     }
 
@@ -104,144 +169,313 @@ impl Packet {
         }
     }
 
-    pub fn calculate(&self) -> u64 {
-        match &self.data {
-            PacketType::Literal(literal) => *literal as u64,
-            PacketType::Operator(operator) => operator.calculate(),
+    /// Calculates the value of the packet, fails if an operation overflows
+    pub fn calculate(&self) -> anyhow::Result<u64> {
+        self.evaluate(0, None)
+    }
+
+    /// Calculates the value of the packet step by step, returns the value and a line for
+    /// every evaluated operator. Sub packets come first, indented by their depth.
+    pub fn trace(&self) -> anyhow::Result<(u64, Vec<String>)> {
+        let mut steps = Vec::new();
+        let value = self.evaluate(0, Some(&mut steps))?;
+        Ok((value, steps))
+    }
+
+    fn evaluate(&self, depth: usize, mut steps: Option<&mut Vec<String>>) -> anyhow::Result<u64> {
+        let operator = match &self.data {
+            PacketType::Literal { value, .. } => return Ok(*value),
+            PacketType::Operator(operator) => operator,
+        };
+
+        let values = operator
+            .packets
+            .iter()
+            .map(|packet| packet.evaluate(depth + 1, steps.as_deref_mut()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let value = operator.kind.apply(&values)?;
+        if let Some(steps) = steps {
+            steps.push(format!(
+                "{}{} = {}",
+                "  ".repeat(depth),
+                operator.kind.format(&values),
+                value
+            ));
+        }
+        Ok(value)
+    }
+
+    /// Returns the packet as expression with the version of every packet, e.g.
+    /// `max(3<v1>, 7<v2>)<v3>`
+    pub fn annotated(&self) -> String {
+        self.expression(true).0
+    }
+
+    /// Returns the packet as expression and the precedence of its outermost operator
+    fn expression(&self, versions: bool) -> (String, u8) {
+        let annotate = |expression: String| {
+            if versions {
+                format!("{}<v{}>", expression, self.version)
+            } else {
+                expression
+            }
+        };
+
+        let operator = match &self.data {
+            PacketType::Literal { value, .. } => return (annotate(value.to_string()), ATOM),
+            PacketType::Operator(operator) => operator,
+        };
+        let symbol = match operator.kind.symbol() {
+            Some(symbol) if operator.packets.len() >= 2 => symbol,
+            _ => {
+                let args = operator
+                    .packets
+                    .iter()
+                    .map(|packet| packet.expression(versions).0)
+                    .join(", ");
+                return (
+                    annotate(format!("{}({})", operator.kind.name(), args)),
+                    ATOM,
+                );
+            }
+        };
+
+        // comparisons are not chained, all other operators are associative
+        let precedence = operator.kind.precedence();
+        let expression = operator
+            .packets
+            .iter()
+            .map(|packet| {
+                let (expression, inner) = packet.expression(versions);
+                if inner < precedence || (inner == precedence && precedence == 0) {
+                    format!("({})", expression)
+                } else {
+                    expression
+                }
+            })
+            .join(&format!(" {} ", symbol));
+        if versions {
+            (annotate(format!("({})", expression)), ATOM)
+        } else {
+            (expression, precedence)
         }
     }
 }
 
+impl Display for Packet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.expression(false).0)
+    }
+}
+
 impl Packet {
     pub fn count_version(&self) -> usize {
         let count = match &self.data {
             PacketType::Operator(operator) => operator.count_version(),
             _ => 0,
         };
-        self.version as usize + count as usize
+        self.version as usize + count
     }
 }
 
-/// A basic cursor that reads the binary stream sequentially, handles internal cursor
-#[derive(Debug)]
-struct BinaryCursor {
-    /// Holds all binary data, converted from char, each entry is either '0' or '1'
-    pub bytes: Vec<u8>,
-    /// Index into the String
-    index: usize,
+/// Converts a buffered stream of hexadecimal characters into the bytes they encode, ignores
+/// whitespace
+struct HexReader<R> {
+    chars: Bytes<R>,
+    /// An error that came up after some bytes were decoded, reported by the next read
+    error: Option<std::io::Error>,
 }
 
-impl<'a> BinaryCursor {
-    pub fn new(bytes: &[u8]) -> Self {
+impl<R: BufRead> HexReader<R> {
+    pub fn new(reader: R) -> Self {
         Self {
-            bytes: bytes.iter().cloned().collect_vec(),
-            index: 0,
+            chars: reader.bytes(),
+            error: None,
         }
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.index >= self.bytes.len() - 1
+    /// Returns the value of the next hex digit, `None` at the end of the stream
+    fn next_digit(&mut self) -> std::io::Result<Option<u8>> {
+        for c in self.chars.by_ref() {
+            let c = c? as char;
+            if c.is_ascii_whitespace() {
+                continue;
+            }
+            return match c.to_digit(16) {
+                Some(digit) => Ok(Some(digit as u8)),
+                None => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Invalid hex digit '{}'", c),
+                )),
+            };
+        }
+        Ok(None)
     }
 
-    // 11010010_11111110_00101000
-    pub fn read_bits(&mut self, bits: usize) -> anyhow::Result<u16> {
-        assert!(bits <= 16);
+    /// Returns the next byte made of two hex digits, `None` at the end of the stream
+    fn next_byte(&mut self) -> std::io::Result<Option<u8>> {
+        let Some(high) = self.next_digit()? else {
+            return Ok(None);
+        };
+        // an odd number of digits is padded with zeros
+        let low = self.next_digit()?.unwrap_or(0);
+        Ok(Some((high << 4) | low))
+    }
+}
 
-        // TODO refactor later, it's a bit cluttered
-        let mut result = 0_u16;
-        for i in 0..bits {
-            let byte_index = self.index + i;
+impl<R: BufRead> Read for HexReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
 
-            // parse current char
-            let value = self.bytes[byte_index];
-            if value == 1 {
-                result = result | 1_u16.shl(bits - 1 - i);
+        let mut written = 0;
+        while written < buf.len() {
+            match self.next_byte() {
+                Ok(Some(byte)) => buf[written] = byte,
+                Ok(None) => break,
+                // the bytes decoded so far are still returned
+                Err(error) if written > 0 => {
+                    self.error = Some(error);
+                    break;
+                }
+                Err(error) => return Err(error),
             }
+            written += 1;
         }
-        self.index += bits;
+        Ok(written)
+    }
+}
+
+/// A cursor that reads bits sequentially from a byte stream, most significant bit first.
+///
+/// Only the bits not consumed yet of the last read bytes are kept in memory.
+#[derive(Debug)]
+struct BinaryCursor<R> {
+    reader: R,
+    /// Bits read from the stream but not consumed yet, in the lowest `buffered` bits
+    buffer: u128,
+    buffered: usize,
+    /// The number of bits consumed so far
+    position: usize,
+}
 
-        Ok(result)
+impl<R: Read> BinaryCursor<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: 0,
+            buffered: 0,
+            position: 0,
+        }
     }
 
-    /// Skips the number of bits in the Binary stream
-    pub fn skip_bits(&mut self, num_bits: u16) {
-        self.index += num_bits as usize;
+    /// Returns the number of bits read so far
+    pub fn position(&self) -> usize {
+        self.position
     }
 
-    pub fn slice(&self, next_bits: u16) -> &[u8] {
-        &self.bytes[self.index..][..next_bits as usize]
+    /// Reads the next bits as number, up to 64 bits at a time
+    pub fn read_bits(&mut self, bits: usize) -> anyhow::Result<u64> {
+        if bits > 64 {
+            bail!("Can not read {} bits at once, at most 64", bits);
+        }
+
+        while self.buffered < bits {
+            let mut byte = [0_u8];
+            self.reader.read_exact(&mut byte).with_context(|| {
+                format!("Unexpected end of transmission at bit {}", self.position)
+            })?;
+            self.buffer = (self.buffer << 8) | byte[0] as u128;
+            self.buffered += 8;
+        }
+
+        self.buffered -= bits;
+        let value = (self.buffer >> self.buffered) & ((1_u128 << bits) - 1);
+        self.buffer &= (1_u128 << self.buffered) - 1;
+        self.position += bits;
+        Ok(value as u64)
     }
 }
 
-impl Display for BinaryCursor {
+impl<R> Display for BinaryCursor<R> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let bytes = self.bytes.iter().map(|x| format!("{:b}", x)).join("");
-        write!(f, "Cursor {{ index: {}, bytes: {} }}", self.index, bytes)
+        write!(
+            f,
+            "Cursor {{ position: {}, buffered: {:0width$b} }}",
+            self.position,
+            self.buffer,
+            width = self.buffered
+        )
     }
 }
 
-impl From<&str> for BinaryCursor {
+impl From<&str> for BinaryCursor<Cursor<Vec<u8>>> {
+    /// Creates a cursor from a binary String with '0' and '1', packed into bytes
     fn from(input: &str) -> Self {
         let bytes = input
             .chars()
             .filter_map(|b| b.to_digit(2))
-            .map(|v| v as u8)
+            .chunks(8)
+            .into_iter()
+            .map(|byte| {
+                let byte = byte.collect_vec();
+                let value = byte.iter().fold(0, |value, &bit| (value << 1) | bit as u8);
+                value << (8 - byte.len())
+            })
             .collect_vec();
-        Self::new(&bytes)
+        Self::new(Cursor::new(bytes))
     }
 }
 
 /// Parser struct is to read specific elements from the binary stream
 #[derive(Debug)]
-struct Parser {
-    pub cursor: BinaryCursor,
+struct Parser<R> {
+    pub cursor: BinaryCursor<R>,
 }
 
-impl Parser {
-    pub fn new(bytes: &[u8]) -> Self {
+impl<R: Read> Parser<R> {
+    pub fn new(reader: R) -> Self {
         Self {
-            cursor: BinaryCursor::new(bytes),
+            cursor: BinaryCursor::new(reader),
         }
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.cursor.is_empty()
+    pub fn position(&self) -> usize {
+        self.cursor.position()
     }
 
-    pub fn read_bits(&mut self, bits: usize) -> anyhow::Result<u16> {
+    pub fn read_bits(&mut self, bits: usize) -> anyhow::Result<u64> {
         self.cursor.read_bits(bits)
     }
 
     pub fn read_header(&mut self) -> anyhow::Result<(u16, u16)> {
-        let version = self.cursor.read_bits(3)?;
-        let type_id = self.cursor.read_bits(3)?;
+        let version = self.cursor.read_bits(3)? as u16;
+        let type_id = self.cursor.read_bits(3)? as u16;
         Ok((version, type_id))
     }
 
-    /// Reads the literal in 5 bits chunk until completes.
-    pub fn read_literal(&mut self) -> anyhow::Result<u64> {
+    /// Reads the literal in 5 bits chunk until completes, returns it with the number of chunks.
+    pub fn read_literal(&mut self) -> anyhow::Result<(u64, usize)> {
         let mut result = 0_u64;
+        let mut groups = 0;
         loop {
-            let bits = self.cursor.read_bits(5)? as u64;
+            groups += 1;
+            let bits = self.cursor.read_bits(5)?;
+            if result.leading_zeros() < 4 {
+                bail!("Literal does not fit into 64 bits");
+            }
             result = result.shl(4) + (bits & 0xF);
             if bits & 0b10000 >= 1 {
                 continue;
             }
             break;
         }
-        Ok(result)
-    }
-
-    pub fn slice(&self, next_bits: u16) -> &[u8] {
-        self.cursor.slice(next_bits)
-    }
-
-    pub fn skip_bits(&mut self, num_bits: u16) {
-        self.cursor.skip_bits(num_bits);
+        Ok((result, groups))
     }
 }
 
-impl From<&str> for Parser {
+impl From<&str> for Parser<Cursor<Vec<u8>>> {
     /// Creates a new Parser from a binary String with '0' and '1'
     fn from(input: &str) -> Self {
         Self {
@@ -250,43 +484,48 @@ impl From<&str> for Parser {
     }
 }
 
-impl Display for Parser {
+impl<R> Display for Parser<R> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Parser {{ {} }}", self.cursor)
     }
 }
 
 #[derive(Debug)]
-struct BinaryReader {
-    input: String,
+struct BinaryReader<R> {
+    parser: Parser<R>,
 }
 
-impl BinaryReader {
-    /// Creates a new BinaryReader with binary input (a string consisting of '0' and '1')
-    pub fn new(input: String) -> Self {
-        Self { input }
+impl<R: Read> BinaryReader<R> {
+    /// Creates a new BinaryReader that reads the packet bits from the byte stream
+    pub fn new(reader: R) -> Self {
+        Self {
+            parser: Parser::new(reader),
+        }
     }
 
-    pub fn decode(&self) -> Result<Packet, anyhow::Error> {
-        let mut parser = Parser::from(self.input.as_str());
-        let packet = Self::read_packet(&mut parser)?;
+    pub fn decode(mut self) -> Result<Packet, anyhow::Error> {
+        let packet = Self::read_packet(&mut self.parser)?;
         Ok(packet)
     }
 
     // Parses the binary input
-    fn read_packet(parser: &mut Parser) -> Result<Packet, anyhow::Error> {
+    fn read_packet(parser: &mut Parser<R>) -> Result<Packet, anyhow::Error> {
         // read packet header
         let (version, id) = parser.read_header()?;
         let packet = match id {
-            4 => Packet::literal(version, id, parser.read_literal()?),
+            4 => {
+                let (value, groups) = parser.read_literal()?;
+                Packet::literal_in_groups(version, id, value, groups)
+            }
             operator => {
-                let packets = Box::new(Self::read_packets(parser)?);
+                let (packets, length_type) = Self::read_packets(parser)?;
                 Packet::operator(
                     version,
                     id,
                     Operator {
                         packets,
                         kind: OperatorType::from(operator),
+                        length_type,
                     },
                 )
             }
@@ -295,23 +534,24 @@ impl BinaryReader {
         Ok(packet)
     }
 
-    /// Reads all sub packets, returns the list
-    fn read_packets(parser: &mut Parser) -> anyhow::Result<Vec<Packet>> {
+    /// Reads all sub packets, returns the list and how its length was encoded
+    fn read_packets(parser: &mut Parser<R>) -> anyhow::Result<(Vec<Packet>, LengthType)> {
         let mode = parser.read_bits(1)?;
         let packets = if mode == 0 {
-            let total_length = parser.read_bits(15)?;
+            let total_length = parser.read_bits(15)? as usize;
 
             // parse the next number of bits until total length is exhausted
-            let mut sub_parser = Parser::new(parser.slice(total_length));
-
+            let end = parser.position() + total_length;
             let mut result = Vec::new();
-            while !sub_parser.is_empty() {
-                let packet = Self::read_packet(&mut sub_parser)?;
+            while parser.position() < end {
+                let packet = Self::read_packet(parser)?;
                 result.push(packet);
             }
-            parser.skip_bits(total_length);
+            if parser.position() != end {
+                bail!("Sub packets exceed their length of {} bits", total_length);
+            }
 
-            result
+            (result, LengthType::Bits)
         } else {
             let num_packets = parser.read_bits(11)?;
 
@@ -320,22 +560,120 @@ impl BinaryReader {
                 let packet = Self::read_packet(parser)?;
                 sub_packets.push(packet);
             }
-            sub_packets
+            (sub_packets, LengthType::Count)
         };
         Ok(packets)
     }
 }
 
-/// Parses the hexadecimal input string, converts it to binary string
-/// then creates a new BinaryReader to parse all binary data.
-fn parse_hex_input(hexadecimal: &str) -> BinaryReader {
-    let input = hexadecimal
-        .chars()
-        .filter_map(|c| c.to_digit(16))
-        .map(|value| format!("{:04b}", value))
-        .collect_vec()
-        .join("");
-    BinaryReader::new(input)
+/// Writes packets into a binary stream, the inverse of [`BinaryReader`]
+#[derive(Debug, Default)]
+struct BinaryWriter {
+    /// Holds all written bits, each entry is either 0 or 1
+    bits: Vec<u8>,
+}
+
+impl BinaryWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes the lowest `bits` bits of the value, fails if the value does not fit
+    pub fn write_bits(&mut self, value: u64, bits: usize) -> anyhow::Result<()> {
+        if bits < 64 && value >> bits != 0 {
+            bail!("Value {} does not fit into {} bits", value, bits);
+        }
+        self.bits
+            .extend((0..bits).rev().map(|i| ((value >> i) & 1) as u8));
+        Ok(())
+    }
+
+    pub fn write_header(&mut self, version: u16, type_id: u16) -> anyhow::Result<()> {
+        self.write_bits(version as u64, 3)?;
+        self.write_bits(type_id as u64, 3)
+    }
+
+    /// Writes the literal in `groups` 5 bit chunks, the first bit marks if more chunks follow.
+    /// Fails if the literal does not fit.
+    pub fn write_literal(&mut self, literal: u64, groups: usize) -> anyhow::Result<()> {
+        if groups < 16 && literal >> (groups * 4) != 0 {
+            bail!("Literal {} does not fit into {} groups", literal, groups);
+        }
+        for group in (0..groups).rev() {
+            let more = if group > 0 { 0b10000 } else { 0 };
+            let nibble = u32::try_from(group * 4)
+                .ok()
+                .and_then(|shift| literal.checked_shr(shift))
+                .unwrap_or(0);
+            self.write_bits(more | (nibble & 0xF), 5)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_packet(&mut self, packet: &Packet) -> anyhow::Result<()> {
+        self.write_header(packet.version, packet.type_id)?;
+        match &packet.data {
+            PacketType::Literal { value, groups } => self.write_literal(*value, *groups),
+            PacketType::Operator(operator) => {
+                // sub packets are written separately first, their length is needed upfront
+                let mut sub_writer = BinaryWriter::new();
+                for packet in operator.packets.iter() {
+                    sub_writer.write_packet(packet)?;
+                }
+
+                match operator.length_type {
+                    LengthType::Bits => {
+                        self.write_bits(0, 1)?;
+                        self.write_bits(sub_writer.bits.len() as u64, 15)
+                            .context("Too many bits in sub packets")?;
+                    }
+                    LengthType::Count => {
+                        self.write_bits(1, 1)?;
+                        self.write_bits(operator.packets.len() as u64, 11)
+                            .context("Too many sub packets")?;
+                    }
+                }
+                self.bits.extend(sub_writer.bits);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the written bits as hexadecimal string, padded with zeros to full bytes
+    pub fn to_hex(&self) -> String {
+        self.bits
+            .iter()
+            .chain(std::iter::repeat(&0))
+            .take(self.bits.len().div_ceil(8) * 8)
+            .chunks(4)
+            .into_iter()
+            .map(|nibble| {
+                let value = nibble.fold(0, |value, &bit| (value << 1) | bit as u32);
+                std::char::from_digit(value, 16)
+                    .expect("Invalid hex digit")
+                    .to_ascii_uppercase()
+            })
+            .collect()
+    }
+}
+
+impl Packet {
+    /// Encodes the packet into its hexadecimal transmission
+    pub fn encode(&self) -> anyhow::Result<String> {
+        let mut writer = BinaryWriter::new();
+        writer.write_packet(self)?;
+        Ok(writer.to_hex())
+    }
+}
+
+/// Creates a new BinaryReader that decodes the hexadecimal stream while reading it
+fn parse_hex_reader<R: Read>(reader: R) -> BinaryReader<HexReader<BufReader<R>>> {
+    BinaryReader::new(HexReader::new(BufReader::new(reader)))
+}
+
+/// Parses the hexadecimal input string
+fn parse_hex_input(hexadecimal: &str) -> BinaryReader<HexReader<BufReader<&[u8]>>> {
+    parse_hex_reader(hexadecimal.as_bytes())
 }
 
 fn main() -> anyhow::Result<()> {
@@ -346,14 +684,27 @@ fn main() -> anyhow::Result<()> {
     dbg!(packet.count_version());
 
     // second part
-    dbg!(packet.calculate());
+    dbg!(packet.calculate()?);
+    if std::env::args().any(|arg| arg == "--encode") {
+        println!("{}", packet.encode()?);
+    }
+    if std::env::args().any(|arg| arg == "--trace") {
+        println!("{}", packet.annotated());
+        let (_, steps) = packet.trace()?;
+        println!("{}", steps.join("\n"));
+    }
     // result 9864180602 is too low
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{parse_hex_input, BinaryCursor, Operator, OperatorType, Packet, Parser};
+    use std::io::Read;
+
+    use crate::{
+        parse_hex_input, parse_hex_reader, BinaryCursor, BinaryWriter, HexReader, LengthType,
+        Operator, OperatorType, Packet, Parser,
+    };
 
     #[test]
     fn check_cursor_read_bits() -> anyhow::Result<()> {
@@ -374,26 +725,74 @@ mod tests {
         let input = "1101100011";
         let mut parser = Parser::from(input);
         let literal = parser.read_literal()?;
-        assert_eq!(0b10110011, literal);
+        assert_eq!((0b10110011, 2), literal);
         Ok(())
     }
 
     #[test]
-    fn check_parse_cursor_from_string() {
-        let cursor = BinaryCursor::from("110100101111111000101000");
+    fn check_parse_cursor_from_string() -> anyhow::Result<()> {
+        let mut cursor = BinaryCursor::from("110100101111111000101000");
         assert_eq!(
-            vec![1, 1, 0, 1, 0, 0, 1, 0, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 1, 0, 1, 0, 0, 0],
-            cursor.bytes
+            &vec![0b11010010, 0b11111110, 0b00101000],
+            cursor.reader.get_ref()
         );
+        assert_eq!(0b1101001011111110, cursor.read_bits(16)?);
+        assert_eq!(16, cursor.position());
+
+        // the last byte is padded with zeros
+        let cursor = BinaryCursor::from("11010010111111100010100");
+        assert_eq!(
+            &vec![0b11010010, 0b11111110, 0b00101000],
+            cursor.reader.get_ref()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reads_up_to_64_bits() -> anyhow::Result<()> {
+        let bytes = [0xAB, 0xCD, 0xEF, 0x01, 0x23, 0x45, 0x67, 0x89, 0xF0];
+        let mut cursor = BinaryCursor::new(&bytes[..]);
+        assert_eq!(0b1010, cursor.read_bits(4)?);
+        assert_eq!(0xBCDEF0123456789F, cursor.read_bits(64)?);
+        assert_eq!(0, cursor.read_bits(0)?);
+        assert_eq!(0, cursor.read_bits(4)?);
+        assert!(cursor.read_bits(1).is_err());
+        assert!(cursor.read_bits(65).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn decodes_hex_stream() -> anyhow::Result<()> {
+        let mut bytes = Vec::new();
+        HexReader::new("D2 FE2\n8".as_bytes()).read_to_end(&mut bytes)?;
+        assert_eq!(vec![0xD2, 0xFE, 0x28], bytes);
+
+        let mut bytes = Vec::new();
+        HexReader::new("ABC".as_bytes()).read_to_end(&mut bytes)?;
+        assert_eq!(vec![0xAB, 0xC0], bytes);
+        assert!(HexReader::new("AX".as_bytes())
+            .read_to_end(&mut bytes)
+            .is_err());
+
+        // the bytes before an invalid digit are returned, the error comes with the next read
+        let mut reader = HexReader::new("D2FXE".as_bytes());
+        let mut buf = [0; 4];
+        assert_eq!(1, reader.read(&mut buf)?);
+        assert_eq!(0xD2, buf[0]);
+        let err = reader.read(&mut buf).unwrap_err();
+        assert_eq!("Invalid hex digit 'X'", err.to_string());
+
+        let packet = parse_hex_reader("C200B40A82\n".as_bytes()).decode()?;
+        assert_eq!(3, packet.calculate()?);
+        assert!(parse_hex_input("D2FE").decode().is_err());
+        Ok(())
     }
 
     #[test]
     fn decodes_literal_from_hex_input() -> anyhow::Result<()> {
         let reader = parse_hex_input("D2FE28");
-        assert_eq!("110100101111111000101000", reader.input);
-
         let packet = reader.decode()?;
-        assert_eq!(Packet::literal(6, 4, 2021), packet);
+        assert_eq!(literal(6, 4, 2021), packet);
         Ok(())
     }
 
@@ -404,8 +803,9 @@ mod tests {
             1,
             6,
             Operator {
-                packets: Box::new(vec![Packet::literal(6, 4, 10), Packet::literal(2, 4, 20)]),
+                packets: vec![literal(6, 4, 10), literal(2, 4, 20)],
                 kind: OperatorType::LessThan,
+                length_type: LengthType::Bits,
             },
         );
 
@@ -420,12 +820,9 @@ mod tests {
             7,
             3,
             Operator {
-                packets: Box::new(vec![
-                    Packet::literal(2, 4, 1),
-                    Packet::literal(4, 4, 2),
-                    Packet::literal(1, 4, 3),
-                ]),
+                packets: vec![literal(2, 4, 1), literal(4, 4, 2), literal(1, 4, 3)],
                 kind: OperatorType::Max,
+                length_type: LengthType::Count,
             },
         );
 
@@ -464,20 +861,214 @@ mod tests {
 
     #[test]
     fn calculate_value_of_transmissions() -> anyhow::Result<()> {
-        assert_eq!(3, parse_hex_input("C200B40A82").decode()?.calculate());
-        assert_eq!(54, parse_hex_input("04005AC33890").decode()?.calculate());
-        assert_eq!(7, parse_hex_input("880086C3E88112").decode()?.calculate());
-        assert_eq!(9, parse_hex_input("CE00C43D881120").decode()?.calculate());
-        assert_eq!(1, parse_hex_input("D8005AC2A8F0").decode()?.calculate());
-        assert_eq!(0, parse_hex_input("F600BC2D8F").decode()?.calculate());
-        assert_eq!(0, parse_hex_input("9C005AC2F8F0").decode()?.calculate());
+        assert_eq!(3, parse_hex_input("C200B40A82").decode()?.calculate()?);
+        assert_eq!(54, parse_hex_input("04005AC33890").decode()?.calculate()?);
+        assert_eq!(7, parse_hex_input("880086C3E88112").decode()?.calculate()?);
+        assert_eq!(9, parse_hex_input("CE00C43D881120").decode()?.calculate()?);
+        assert_eq!(1, parse_hex_input("D8005AC2A8F0").decode()?.calculate()?);
+        assert_eq!(0, parse_hex_input("F600BC2D8F").decode()?.calculate()?);
+        assert_eq!(0, parse_hex_input("9C005AC2F8F0").decode()?.calculate()?);
         assert_eq!(
             1,
             parse_hex_input("9C0141080250320F1802104A08")
                 .decode()?
-                .calculate()
+                .calculate()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn encodes_packets() -> anyhow::Result<()> {
+        let mut writer = BinaryWriter::new();
+        writer.write_literal(2021, 3)?;
+        assert_eq!("BF8A", writer.to_hex());
+        assert_eq!("D2FE28", literal(6, 4, 2021).encode()?);
+        assert!(writer.write_literal(2021, 2).is_err());
+
+        // leading zero groups are kept, D201 has one before the value 1
+        let packet = parse_hex_input("D201").decode()?;
+        assert_eq!(Packet::literal_in_groups(6, 4, 1, 2), packet);
+        assert_eq!("D201", packet.encode()?);
+
+        let mut writer = BinaryWriter::new();
+        assert!(writer.write_bits(8, 3).is_err());
+        assert!(writer.write_header(8, 4).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn round_trips_transmissions() -> anyhow::Result<()> {
+        for input in [
+            "D2FE28",
+            "38006F45291200",
+            "EE00D40C823060",
+            "8A004A801A8002F478",
+            "620080001611562C8802118E34",
+            "C0015000016115A2E0802F182340",
+            "A0016C880162017C3686B18A3D4780",
+            "9C0141080250320F1802104A08",
+            include_str!("input.txt").trim(),
+        ] {
+            assert_eq!(input, parse_hex_input(input).decode()?.encode()?);
+        }
+        Ok(())
+    }
+
+    /// Creates a literal packet with the fewest 4 bit groups that hold the value
+    fn literal(version: u16, type_id: u16, value: u64) -> Packet {
+        let groups = (64 - value.leading_zeros() as usize).div_ceil(4).max(1);
+        Packet::literal_in_groups(version, type_id, value, groups)
+    }
+
+    /// Generates a random packet tree with a simple xorshift generator
+    fn random_packet(seed: &mut u64, depth: usize) -> Packet {
+        let mut next = |max: u64| {
+            *seed ^= *seed << 13;
+            *seed ^= *seed >> 7;
+            *seed ^= *seed << 17;
+            *seed % max
+        };
+
+        let version = next(8) as u16;
+        if depth == 0 || next(3) == 0 {
+            let bits = next(65) as u32;
+            let value = next(u64::MAX).checked_shr(64 - bits).unwrap_or(0);
+            let minimal = (bits as usize).div_ceil(4).max(1);
+            // some literals are padded with leading zero groups
+            let padding = if next(4) == 0 {
+                1 + next(20) as usize
+            } else {
+                0
+            };
+            return Packet::literal_in_groups(version, 4, value, minimal + padding);
+        }
+
+        let type_id = [0, 1, 2, 3, 5, 6, 7][next(7) as usize];
+        let length_type = if next(2) == 0 {
+            LengthType::Bits
+        } else {
+            LengthType::Count
+        };
+        let count = if type_id >= 5 { 2 } else { 1 + next(4) };
+        let packets = (0..count).map(|_| random_packet(seed, depth - 1)).collect();
+        Packet::operator(
+            version,
+            type_id,
+            Operator {
+                packets,
+                kind: OperatorType::from(type_id),
+                length_type,
+            },
+        )
+    }
+
+    #[test]
+    fn fuzz_decoder_with_encoded_packets() -> anyhow::Result<()> {
+        let mut seed = 0x2545_f491_4f6c_dd1d;
+        for _ in 0..500 {
+            let packet = random_packet(&mut seed, 4);
+            let hex = packet.encode()?;
+            assert_eq!(packet, parse_hex_input(&hex).decode()?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn prints_expressions() -> anyhow::Result<()> {
+        // max(3, 7 * (2 + 5)) > 9
+        let sum = Packet::operator(
+            4,
+            0,
+            Operator {
+                packets: vec![literal(5, 4, 2), literal(6, 4, 5)],
+                kind: OperatorType::Sum,
+                length_type: LengthType::Bits,
+            },
+        );
+        let product = Packet::operator(
+            3,
+            1,
+            Operator {
+                packets: vec![literal(7, 4, 7), sum],
+                kind: OperatorType::Product,
+                length_type: LengthType::Count,
+            },
+        );
+        let max = Packet::operator(
+            2,
+            3,
+            Operator {
+                packets: vec![literal(1, 4, 3), product],
+                kind: OperatorType::Max,
+                length_type: LengthType::Bits,
+            },
+        );
+        let packet = Packet::operator(
+            0,
+            5,
+            Operator {
+                packets: vec![max, literal(1, 4, 9)],
+                kind: OperatorType::GreaterThan,
+                length_type: LengthType::Count,
+            },
+        );
+
+        assert_eq!("max(3, 7 * (2 + 5)) > 9", packet.to_string());
+        assert_eq!(
+            "(max(3<v1>, (7<v7> * (2<v5> + 5<v6>)<v4>)<v3>)<v2> > 9<v1>)<v0>",
+            packet.annotated()
+        );
+
+        let (value, steps) = packet.trace()?;
+        assert_eq!(1, value);
+        assert_eq!(
+            vec![
+                "      2 + 5 = 7",
+                "    7 * 7 = 49",
+                "  max(3, 49) = 49",
+                "49 > 9 = 1"
+            ],
+            steps
+        );
+
+        assert_eq!(
+            "1 + 3 == 2 * 2",
+            parse_hex_input("9C0141080250320F1802104A08")
+                .decode()?
+                .to_string()
         );
+        Ok(())
+    }
 
+    #[test]
+    fn reports_overflow() -> anyhow::Result<()> {
+        let packet = Packet::operator(
+            0,
+            1,
+            Operator {
+                packets: vec![literal(0, 4, u64::MAX / 2), literal(0, 4, 3)],
+                kind: OperatorType::Product,
+                length_type: LengthType::Bits,
+            },
+        );
+        let err = packet.calculate().unwrap_err();
+        assert_eq!(
+            format!("Overflow in product of {} * 3", u64::MAX / 2),
+            err.to_string()
+        );
+        assert!(packet.trace().is_err());
+
+        let packet = Packet::operator(
+            0,
+            0,
+            Operator {
+                packets: vec![literal(0, 4, u64::MAX), literal(0, 4, 1)],
+                kind: OperatorType::Sum,
+                length_type: LengthType::Bits,
+            },
+        );
+        assert!(packet.calculate().is_err());
         Ok(())
     }
 }